@@ -0,0 +1,89 @@
+use std::fs::{self, File, Metadata};
+use std::io::{self, Read};
+use std::path::Path;
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::manifest::ManifestEntry;
+
+/// How a file in the source is compared against its copy in the target.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CompareMode {
+    /// Copy when the source was modified after the target copy.
+    #[default]
+    Mtime,
+    /// Copy when the sizes differ or the source was modified after the
+    /// target copy.
+    #[value(name = "size+mtime")]
+    #[serde(rename = "size+mtime")]
+    SizeMtime,
+    /// Copy only when the contents differ, using BLAKE3 hashes.
+    Checksum,
+}
+
+/// Returns whether `src` differs from the existing file `dst` under `mode`.
+pub(crate) fn needs_copy(src: &Path, dst: &Path, mode: CompareMode) -> io::Result<bool> {
+    let source = fs::metadata(src)?;
+    let target = fs::metadata(dst)?;
+
+    match mode {
+        CompareMode::Mtime => Ok(source.modified()? > target.modified()?),
+        CompareMode::SizeMtime => {
+            Ok(source.len() != target.len() || source.modified()? > target.modified()?)
+        }
+        CompareMode::Checksum => {
+            if source.len() != target.len() {
+                return Ok(true);
+            }
+            Ok(hash_file(src)? != hash_file(dst)?)
+        }
+    }
+}
+
+/// Returns whether the source file `src`, with metadata `source`, differs
+/// from the version recorded in a manifest under `mode`.
+///
+/// Unlike [`needs_copy`] this never touches the target copy.
+pub(crate) fn needs_copy_from_manifest(
+    src: &Path,
+    source: &Metadata,
+    entry: &ManifestEntry,
+    mode: CompareMode,
+) -> io::Result<bool> {
+    match mode {
+        CompareMode::Mtime => Ok(source.modified()? > entry.modified()),
+        CompareMode::SizeMtime => {
+            Ok(source.len() != entry.size || source.modified()? > entry.modified())
+        }
+        CompareMode::Checksum => {
+            if source.len() != entry.size {
+                return Ok(true);
+            }
+            Ok(hash_file(src)?.to_hex().as_str() != entry.hash)
+        }
+    }
+}
+
+/// Computes the BLAKE3 hash of a file's contents.
+pub(crate) fn hash_file(path: &Path) -> io::Result<blake3::Hash> {
+    hash_reader(File::open(path)?).map(|(_, hash)| hash)
+}
+
+/// Reads `reader` to the end, returning the number of bytes read and their
+/// BLAKE3 hash.
+pub(crate) fn hash_reader(mut reader: impl Read) -> io::Result<(u64, blake3::Hash)> {
+    let mut hasher = blake3::Hasher::new();
+    let mut len = 0;
+    let mut buffer = [0u8; 65536];
+    loop {
+        let bytes_read = reader.read(&mut buffer)?;
+        if bytes_read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..bytes_read]);
+        len += bytes_read as u64;
+    }
+    Ok((len, hasher.finalize()))
+}
@@ -0,0 +1,156 @@
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use clap::ValueEnum;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use serde::{Deserialize, Serialize};
+
+/// Compression applied to the contents of backed-up files.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    /// Store files as they are.
+    #[default]
+    None,
+    /// Zstandard: fast, with a good ratio.
+    Zstd,
+    /// gzip: slower, but readable with standard tools.
+    Gzip,
+}
+
+impl Compression {
+    pub fn is_none(&self) -> bool {
+        *self == Compression::None
+    }
+
+    /// Level used when none is given: 3 for zstd and 6 for gzip.
+    pub fn default_level(&self) -> i32 {
+        match self {
+            Compression::None => 0,
+            Compression::Zstd => zstd::DEFAULT_COMPRESSION_LEVEL,
+            Compression::Gzip => 6,
+        }
+    }
+}
+
+/// Extensions of formats that are compressed already, which are stored as
+/// they are.
+const COMPRESSED_EXTENSIONS: &[&str] = &[
+    "7z", "aac", "apk", "avi", "br", "bz2", "docx", "flac", "gif", "gz", "heic", "jar", "jpeg",
+    "jpg", "lz4", "m4a", "mkv", "mov", "mp3", "mp4", "odt", "ogg", "opus", "png", "pptx", "rar",
+    "tgz", "txz", "webm", "webp", "xlsx", "xz", "zip", "zst",
+];
+
+/// Bytes read from the start of a file to estimate how well it compresses.
+const SAMPLE_SIZE: u64 = 64 * 1024;
+
+/// Entropy, in bits per byte, above which data is treated as
+/// incompressible.
+const MAX_ENTROPY: f64 = 7.5;
+
+/// Returns whether compressing `path` is unlikely to save space, judging by
+/// its extension or else by the entropy of its first bytes.
+pub(crate) fn is_incompressible(path: &Path) -> io::Result<bool> {
+    if let Some(extension) = path.extension().and_then(|e| e.to_str()) {
+        if COMPRESSED_EXTENSIONS
+            .iter()
+            .any(|known| known.eq_ignore_ascii_case(extension))
+        {
+            return Ok(true);
+        }
+    }
+
+    let mut sample = Vec::new();
+    File::open(path)?
+        .take(SAMPLE_SIZE)
+        .read_to_end(&mut sample)?;
+    Ok(entropy(&sample) > MAX_ENTROPY)
+}
+
+/// Shannon entropy of `data` in bits per byte, from 0 to 8.
+fn entropy(data: &[u8]) -> f64 {
+    if data.is_empty() {
+        return 0.0;
+    }
+    let mut counts = [0usize; 256];
+    for &byte in data {
+        counts[byte as usize] += 1;
+    }
+    let len = data.len() as f64;
+    counts
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// A writer compressing into another writer.
+pub(crate) enum Encoder<W: Write> {
+    Plain(W),
+    Zstd(zstd::Encoder<'static, W>),
+    Gzip(GzEncoder<W>),
+}
+
+impl<W: Write> Encoder<W> {
+    /// Starts writing `file` with `compression` at `level`, which is clamped
+    /// to the range the format supports.
+    pub(crate) fn new(file: W, compression: Compression, level: i32) -> io::Result<Self> {
+        Ok(match compression {
+            Compression::None => Encoder::Plain(file),
+            Compression::Zstd => {
+                let range = zstd::compression_level_range();
+                let level = level.clamp(*range.start(), *range.end());
+                Encoder::Zstd(zstd::Encoder::new(file, level)?)
+            }
+            Compression::Gzip => {
+                let level = flate2::Compression::new(level.clamp(0, 9) as u32);
+                Encoder::Gzip(GzEncoder::new(file, level))
+            }
+        })
+    }
+
+    /// Completes the compressed stream and returns the inner writer.
+    pub(crate) fn finish(self) -> io::Result<W> {
+        match self {
+            Encoder::Plain(file) => Ok(file),
+            Encoder::Zstd(encoder) => encoder.finish(),
+            Encoder::Gzip(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Encoder::Plain(file) => file.write(buf),
+            Encoder::Zstd(encoder) => encoder.write(buf),
+            Encoder::Gzip(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Encoder::Plain(file) => file.flush(),
+            Encoder::Zstd(encoder) => encoder.flush(),
+            Encoder::Gzip(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Returns a reader yielding the original contents of data stored with
+/// `compression`.
+pub(crate) fn decoder<'a, R: Read + 'a>(
+    reader: R,
+    compression: Compression,
+) -> io::Result<Box<dyn Read + 'a>> {
+    Ok(match compression {
+        Compression::None => Box::new(reader),
+        Compression::Zstd => Box::new(zstd::Decoder::new(reader)?),
+        Compression::Gzip => Box::new(GzDecoder::new(reader)),
+    })
+}
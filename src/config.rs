@@ -0,0 +1,202 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Deserializer};
+
+use crate::compare::CompareMode;
+use crate::compress::Compression;
+use crate::error::{Error, Result};
+use crate::prune::RetentionPolicy;
+use crate::symlink::SymlinkMode;
+
+/// Named backup profiles read from a TOML file.
+///
+/// ```toml
+/// [profiles.documents]
+/// source = "~/Documents"
+/// target = "/mnt/backup/documents"
+/// exclude = ["*.tmp", "node_modules"]
+/// compare = "checksum"
+/// snapshot = true
+///
+/// [profiles.documents.retention]
+/// keep_daily = 7
+/// keep_weekly = 4
+///
+/// [profiles.system]
+/// source = ["/etc", "/srv"]
+/// target = "/mnt/backup/system"
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+/// One backup pair with its options, as the command line would give them.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    /// One directory, or a list of directories each backed up into its
+    /// own subtree of the target.
+    #[serde(deserialize_with = "deserialize_sources")]
+    pub source: Vec<PathBuf>,
+    pub target: PathBuf,
+    #[serde(default)]
+    pub include: Vec<String>,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    #[serde(default)]
+    pub compare: CompareMode,
+    #[serde(default)]
+    pub symlinks: SymlinkMode,
+    #[serde(default)]
+    pub safe_links: bool,
+    /// Preserve timestamps, permissions and ownership.
+    #[serde(default)]
+    pub archive: bool,
+    #[serde(default)]
+    pub xattrs: bool,
+    #[serde(default)]
+    pub acls: bool,
+    #[serde(default = "default_jobs")]
+    pub jobs: u16,
+    #[serde(default)]
+    pub snapshot: bool,
+    #[serde(default)]
+    pub repository: bool,
+    #[serde(default)]
+    pub delete: bool,
+    /// Largest percentage of the target's files `delete` may remove in
+    /// one run.
+    #[serde(default = "default_max_delete")]
+    pub max_delete: u8,
+    #[serde(default)]
+    pub compress: Compression,
+    #[serde(default)]
+    pub compress_level: Option<i32>,
+    /// Encrypt with a passphrase from the environment or the terminal.
+    #[serde(default)]
+    pub encrypt: bool,
+    /// Encrypt with the contents of this file.
+    #[serde(default)]
+    pub key_file: Option<PathBuf>,
+    #[serde(default)]
+    pub encrypt_names: bool,
+    /// Also write a JSON report of each run to this file.
+    #[serde(default)]
+    pub report_file: Option<PathBuf>,
+    /// Snapshots to keep when pruning after each run; empty to never prune.
+    /// Only allowed with `snapshot` or `repository`.
+    #[serde(default)]
+    pub retention: RetentionPolicy,
+}
+
+fn default_jobs() -> u16 {
+    1
+}
+
+fn default_max_delete() -> u8 {
+    50
+}
+
+impl Config {
+    /// Returns the config file used when none is given:
+    /// `$XDG_CONFIG_HOME/srb/config.toml`, falling back to
+    /// `~/.config/srb/config.toml`, or `%APPDATA%\srb\config.toml` on
+    /// Windows.
+    pub fn default_path() -> Option<PathBuf> {
+        let dir = if cfg!(windows) {
+            env::var_os("APPDATA").map(PathBuf::from)
+        } else {
+            env::var_os("XDG_CONFIG_HOME")
+                .map(PathBuf::from)
+                .filter(|dir| dir.is_absolute())
+                .or_else(|| home_dir().map(|home| home.join(".config")))
+        };
+        dir.map(|dir| dir.join("srb").join("config.toml"))
+    }
+
+    /// Reads the config file at `path`.
+    ///
+    /// Paths in profiles may start with `~/`, for the home directory, and
+    /// relative ones are taken from the directory holding the config file.
+    pub fn load(path: &Path) -> Result<Config> {
+        let config_error = |message: String| Error::Config(path.to_path_buf(), message);
+
+        let text = fs::read_to_string(path).map_err(|e| config_error(e.to_string()))?;
+        let mut config: Config = toml::from_str(&text).map_err(|e| config_error(e.to_string()))?;
+
+        let base = path.parent().unwrap_or(Path::new("."));
+        for (name, profile) in &mut config.profiles {
+            // Pruning a plain target would remove backed-up data
+            if !profile.retention.is_empty() && !profile.snapshot && !profile.repository {
+                return Err(config_error(format!(
+                    "profile {:?} has a retention policy but writes neither snapshots nor a repository",
+                    name
+                )));
+            }
+            if profile.max_delete > 100 {
+                return Err(config_error(format!(
+                    "profile {:?} has max_delete {}, which is above 100",
+                    name, profile.max_delete
+                )));
+            }
+            for source in &mut profile.source {
+                *source = resolve(base, source);
+            }
+            profile.target = resolve(base, &profile.target);
+            if let Some(key_file) = &profile.key_file {
+                profile.key_file = Some(resolve(base, key_file));
+            }
+            if let Some(report_file) = &profile.report_file {
+                profile.report_file = Some(resolve(base, report_file));
+            }
+        }
+        Ok(config)
+    }
+
+    /// Returns the profile called `name`.
+    pub fn profile(&self, name: &str) -> Result<&Profile> {
+        self.profiles
+            .get(name)
+            .ok_or_else(|| Error::ProfileNotFound(name.to_string()))
+    }
+}
+
+/// Reads a single source path or a non-empty list of them.
+fn deserialize_sources<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Vec<PathBuf>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Sources {
+        One(PathBuf),
+        Many(Vec<PathBuf>),
+    }
+
+    match Sources::deserialize(deserializer)? {
+        Sources::One(source) => Ok(vec![source]),
+        Sources::Many(sources) if sources.is_empty() => {
+            Err(serde::de::Error::custom("expected at least one source"))
+        }
+        Sources::Many(sources) => Ok(sources),
+    }
+}
+
+fn home_dir() -> Option<PathBuf> {
+    env::var_os(if cfg!(windows) { "USERPROFILE" } else { "HOME" }).map(PathBuf::from)
+}
+
+/// Expands a leading `~` and makes `path` relative to `base`.
+fn resolve(base: &Path, path: &Path) -> PathBuf {
+    if let Ok(rest) = path.strip_prefix("~") {
+        if let Some(home) = home_dir() {
+            return home.join(rest);
+        }
+    }
+    base.join(path)
+}
@@ -0,0 +1,323 @@
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+
+use crate::compress::{decoder, Compression, Encoder};
+use crate::crypto::{decrypter, Cipher, Encrypter};
+use crate::metadata::Preserve;
+use crate::sparse::{copy_sparse, data_regions};
+use crate::xattrs::{apply_attributes, read_xattrs};
+
+#[cfg(target_os = "windows")]
+pub(crate) fn remove_readonly_attribute(target_path: &Path) -> std::io::Result<()> {
+    let metadata = fs::metadata(target_path)?;
+    let mut permissions = metadata.permissions();
+
+    // Check if the file is read-only
+    if permissions.readonly() {
+        permissions.set_readonly(false);
+        fs::set_permissions(target_path, permissions)?;
+    }
+
+    Ok(())
+}
+
+/// Creates the progress display, hidden unless `enabled`, together with the
+/// overall bar counting `len` files.
+pub(crate) fn progress_bars(enabled: bool, len: u64) -> (MultiProgress, ProgressBar) {
+    // Create a MultiProgress to manage multiple progress bars
+    let mp = if enabled {
+        MultiProgress::new()
+    } else {
+        MultiProgress::with_draw_target(ProgressDrawTarget::hidden())
+    };
+
+    // Create the overall progress bar
+    let pb = mp.add(ProgressBar::new(len));
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} {msg}")
+            .expect("Failed to set progress bar template")
+            .progress_chars("#>-"),
+    );
+
+    (mp, pb)
+}
+
+/// Adds a progress bar for one file of `len` bytes, labelled with `verb`
+/// and the file's relative path.
+pub(crate) fn file_progress_bar(
+    mp: &MultiProgress,
+    len: u64,
+    verb: &'static str,
+    relative_path: &Path,
+) -> ProgressBar {
+    // Create the per-file progress bar using MultiProgress
+    let pb = mp.add(ProgressBar::new(len));
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template("{spinner:.green} {prefix} {msg}\n  {bar:40.cyan/blue} {bytes}/{total_bytes} ({bytes_per_sec}, ETA: {eta})")
+            .expect("Failed to set per-file progress bar template")
+            .progress_chars("#>-"),
+    );
+
+    // Set the message to the filename
+    pb.set_prefix(verb);
+    pb.set_message(format!("{:?}", relative_path));
+    pb
+}
+
+/// How file contents change on the way from `src` to `dst` in
+/// [`copy_with_progress`].
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) enum Transform<'a> {
+    /// Copy the bytes as they are.
+    #[default]
+    None,
+    /// Compress at the given level, then encrypt when there is a cipher,
+    /// while writing.
+    Encode(Compression, i32, Option<&'a Cipher>),
+    /// Decrypt when there is a cipher, then decompress, while reading.
+    Decode(Compression, Option<&'a Cipher>),
+}
+
+/// Copies `src` to `dst`, showing a per-file progress bar labelled with
+/// `verb`, and returns the number of plaintext bytes copied together with
+/// their BLAKE3 hash.
+///
+/// The data is written to a temporary sibling of `dst` which is synced and
+/// then renamed over `dst`, so an interrupted copy never leaves a partially
+/// written file in place of the previous version.
+///
+/// When `expected` is given, the copy only replaces `dst` if its hash is the
+/// expected one in hex, so damaged data never overwrites a good file.
+///
+/// Holes in a sparse `src` copied without a transform stay holes in `dst`.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn copy_with_progress(
+    src: &Path,
+    dst: &Path,
+    relative_path: &Path,
+    transform: Transform,
+    preserve: Preserve,
+    expected: Option<&str>,
+    verb: &'static str,
+    mp: &MultiProgress,
+) -> io::Result<(u64, blake3::Hash)> {
+    let tmp = temp_path(dst);
+    let result = copy_to_temp(src, &tmp, relative_path, transform, preserve, verb, mp)
+        .and_then(|(copied, hash)| match expected {
+            Some(expected) if hash.to_hex().as_str() != expected => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "copied data does not match the hash in the manifest",
+            )),
+            _ => Ok((copied, hash)),
+        })
+        .and_then(|copied| fs::rename(&tmp, dst).map(|_| copied));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result
+}
+
+const TEMP_SUFFIX: &str = ".srb-tmp";
+
+/// Bytes [`temp_path`] adds to a file name.
+pub(crate) const TEMP_NAME_OVERHEAD: usize = 1 + TEMP_SUFFIX.len();
+
+/// Returns the temporary path used while writing `dst`, e.g.
+/// `dir/.name.srb-tmp` for `dir/name`.
+pub(crate) fn temp_path(dst: &Path) -> PathBuf {
+    let mut name = std::ffi::OsString::from(".");
+    name.push(dst.file_name().unwrap_or_default());
+    name.push(TEMP_SUFFIX);
+    dst.with_file_name(name)
+}
+
+/// Replaces whatever is at `path`, other than a directory, with an entry
+/// made by `create` at the temporary path it is given.
+///
+/// The entry is created under a temporary name and renamed into place, so
+/// `path` is never missing while it is replaced.
+pub(crate) fn replace_with(
+    path: &Path,
+    create: impl FnOnce(&Path) -> io::Result<()>,
+) -> io::Result<()> {
+    if fs::symlink_metadata(path).is_ok_and(|metadata| metadata.is_dir()) {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "a directory is in the way",
+        ));
+    }
+
+    let tmp = temp_path(path);
+    let _ = fs::remove_file(&tmp);
+    let result = create(&tmp).and_then(|_| fs::rename(&tmp, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result
+}
+
+fn copy_to_temp(
+    src: &Path,
+    tmp: &Path,
+    relative_path: &Path,
+    transform: Transform,
+    preserve: Preserve,
+    verb: &'static str,
+    mp: &MultiProgress,
+) -> io::Result<(u64, blake3::Hash)> {
+    let mut input = File::open(src)?;
+    let metadata = input.metadata()?;
+    let total_size = metadata.len();
+
+    let pb = file_progress_bar(mp, total_size, verb, relative_path);
+
+    // Holes in a file copied as it is are recreated instead of written, but
+    // progress still counts them
+    let regions = match transform {
+        Transform::None => data_regions(&input, &metadata),
+        _ => None,
+    };
+    if let Some(regions) = regions {
+        let mut dst_file = File::create(tmp)?;
+        let mut hasher = blake3::Hasher::new();
+        let copied = copy_sparse(
+            &mut input,
+            &mut dst_file,
+            &regions,
+            total_size,
+            &pb,
+            &mut hasher,
+        )?;
+        dst_file.sync_all()?;
+        finish_copy(src, tmp, &metadata, preserve, pb)?;
+        return Ok((copied, hasher.finalize()));
+    }
+
+    let (compression, level, encryption) = match transform {
+        Transform::Encode(compression, level, cipher) => (compression, level, cipher),
+        _ => (Compression::None, 0, None),
+    };
+    let (decompression, decryption) = match transform {
+        Transform::Decode(compression, cipher) => (compression, cipher),
+        _ => (Compression::None, None),
+    };
+    // Looking for holes moves the offset even when it finds none
+    input.rewind()?;
+
+    // Progress follows the bytes read from `src`, before decoding
+    let mut src_file = decoder(decrypter(pb.wrap_read(input), decryption)?, decompression)?;
+    let mut dst_file = Encoder::new(
+        Encrypter::new(File::create(tmp)?, encryption)?,
+        compression,
+        level,
+    )?;
+
+    let mut copied = 0;
+    let mut hasher = blake3::Hasher::new();
+    let mut buffer = [0u8; 8192];
+    loop {
+        let bytes_read = src_file.read(&mut buffer)?;
+        if bytes_read == 0 {
+            break;
+        }
+        dst_file.write_all(&buffer[..bytes_read])?;
+        hasher.update(&buffer[..bytes_read]);
+        copied += bytes_read as u64;
+    }
+
+    dst_file.finish()?.finish()?.sync_all()?;
+
+    finish_copy(src, tmp, &metadata, preserve, pb)?;
+    Ok((copied, hasher.finalize()))
+}
+
+/// Applies the preserved attributes of `src` to the finished copy `tmp`.
+fn finish_copy(
+    src: &Path,
+    tmp: &Path,
+    metadata: &fs::Metadata,
+    preserve: Preserve,
+    pb: ProgressBar,
+) -> io::Result<()> {
+    if !preserve.is_empty() {
+        apply_attributes(metadata, &read_xattrs(src, preserve)?, tmp, preserve)?;
+    }
+
+    pb.finish_and_clear(); // Clear the per-file progress bar and message when done
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::scratch;
+    use std::io::{Seek, SeekFrom};
+
+    const HOLE: u64 = 1 << 20;
+
+    /// Writes a file `len` bytes long holding `data` at each offset of
+    /// `regions` and holes everywhere else.
+    fn write_sparse(path: &Path, len: u64, regions: &[u64], data: &[u8]) {
+        let mut file = File::create(path).unwrap();
+        file.set_len(len).unwrap();
+        for &offset in regions {
+            file.seek(SeekFrom::Start(offset)).unwrap();
+            file.write_all(data).unwrap();
+        }
+    }
+
+    #[test]
+    fn sparse_files_are_copied_exactly() {
+        let dir = scratch("copy-sparse");
+        let data: Vec<u8> = (0..8192).map(|i| (i % 251) as u8 + 1).collect();
+        let block = data.len() as u64;
+        let cases: [(&str, u64, &[u64]); 4] = [
+            ("leading", HOLE + block, &[HOLE]),
+            ("inner", 2 * block + HOLE, &[0, block + HOLE]),
+            ("trailing", block + HOLE, &[0]),
+            ("allocated", 4 * block, &[0, block, 2 * block, 3 * block]),
+        ];
+        let (mp, _) = progress_bars(false, 0);
+
+        for (name, len, regions) in cases {
+            let (src, dst) = (dir.join(name), dir.join(format!("{}.copy", name)));
+            write_sparse(&src, len, regions, &data);
+
+            let (copied, hash) = copy_with_progress(
+                &src,
+                &dst,
+                Path::new(name),
+                Transform::None,
+                Preserve::default(),
+                None,
+                "Copying",
+                &mp,
+            )
+            .unwrap();
+
+            let contents = fs::read(&src).unwrap();
+            assert_eq!(copied, len, "{}", name);
+            assert_eq!(fs::read(&dst).unwrap(), contents, "{}", name);
+            assert_eq!(hash, blake3::hash(&contents), "{}", name);
+
+            #[cfg(target_os = "linux")]
+            {
+                use std::os::unix::fs::MetadataExt;
+
+                // Only a file system that kept the source's holes can keep
+                // the copy's
+                let allocated = |path: &Path| fs::metadata(path).unwrap().blocks() * 512;
+                if name != "allocated" && allocated(&src) < len {
+                    assert!(allocated(&dst) < len, "{} lost its holes", name);
+                }
+            }
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,656 @@
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Write};
+use std::path::{Component, Path, PathBuf};
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::{Aead, AeadInPlace, KeyInit};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use data_encoding::BASE32_DNSSEC;
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+use crate::copy::TEMP_NAME_OVERHEAD;
+use crate::error::{Error, Result};
+use crate::manifest::MANIFEST_DIR;
+
+/// Name of the file inside [`MANIFEST_DIR`] at the top of an encrypted
+/// target, holding the key derivation settings.
+pub const ENCRYPTION_FILE: &str = "encryption.json";
+
+const ENCRYPTION_VERSION: u32 = 1;
+
+/// Start of every encrypted file, followed by the format version.
+const MAGIC: &[u8; 4] = b"SRBE";
+const FORMAT_VERSION: u8 = 1;
+
+/// Random bytes starting the nonce of every segment of a file; the rest is
+/// the segment counter and a flag marking the last segment.
+const NONCE_PREFIX_SIZE: usize = 19;
+
+/// Bytes of plaintext sealed together. Every segment but the last is full,
+/// so a truncated file is detected.
+const SEGMENT_SIZE: usize = 64 * 1024;
+const TAG_SIZE: usize = 16;
+
+/// Longest encrypted file name, leaving room for the temporary name it is
+/// written under within the 255 bytes most filesystems accept.
+const MAX_NAME_LEN: usize = 255 - TEMP_NAME_OVERHEAD;
+
+/// Secret from which the encryption key of a backup is derived.
+#[derive(Clone)]
+pub enum EncryptionKey {
+    Passphrase(String),
+    /// A file whose whole contents serve as the passphrase.
+    File(PathBuf),
+}
+
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncryptionKey::Passphrase(_) => f.write_str("Passphrase(..)"),
+            EncryptionKey::File(path) => f.debug_tuple("File").field(path).finish(),
+        }
+    }
+}
+
+impl EncryptionKey {
+    fn secret(&self) -> io::Result<Zeroizing<Vec<u8>>> {
+        let secret = match self {
+            EncryptionKey::Passphrase(passphrase) => passphrase.as_bytes().to_vec(),
+            EncryptionKey::File(path) => fs::read(path).map_err(|e| {
+                io::Error::new(e.kind(), format!("cannot read key file {:?}: {}", path, e))
+            })?,
+        };
+        if secret.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the passphrase or key file is empty",
+            ));
+        }
+        Ok(Zeroizing::new(secret))
+    }
+}
+
+/// Returns whether the backup at `root` is encrypted.
+pub fn is_encrypted(root: &Path) -> bool {
+    settings_path(root).is_file()
+}
+
+/// Unlocks the backup at `root` for reading with `key`, or returns `None`
+/// when it is not encrypted.
+///
+/// Fails when the key is wrong, when the backup is encrypted and no key is
+/// given, and when a key is given for an unencrypted backup.
+pub(crate) fn unlock(root: &Path, key: Option<&EncryptionKey>) -> Result<Option<Cipher>> {
+    let error =
+        |kind, message| Error::Encryption(root.to_path_buf(), io::Error::new(kind, message));
+    match (key, is_encrypted(root)) {
+        (Some(key), true) => Cipher::open(root, key)
+            .map(Some)
+            .map_err(|e| Error::Encryption(root.to_path_buf(), e)),
+        (Some(_), false) => Err(error(
+            io::ErrorKind::InvalidInput,
+            "the backup is not encrypted",
+        )),
+        (None, true) => Err(error(
+            io::ErrorKind::PermissionDenied,
+            "the backup is encrypted; a passphrase or key file is needed",
+        )),
+        (None, false) => Ok(None),
+    }
+}
+
+fn settings_path(root: &Path) -> PathBuf {
+    root.join(MANIFEST_DIR).join(ENCRYPTION_FILE)
+}
+
+/// Settings fixed when an encrypted target is created.
+///
+/// Only the salt and Argon2 costs are needed to derive the key again; the
+/// check value tells a wrong key apart from damaged data.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Settings {
+    version: u32,
+    /// Hex-encoded Argon2id salt.
+    salt: String,
+    /// Memory cost in KiB.
+    memory_cost: u32,
+    time_cost: u32,
+    parallelism: u32,
+    /// Whether file and directory names are encrypted too.
+    encrypt_names: bool,
+    /// Hex-encoded value derived from the key, compared on unlocking.
+    check: String,
+}
+
+/// Keys for the contents, and optionally the names, of an encrypted backup.
+///
+/// Contents are sealed with XChaCha20-Poly1305 in 64 KiB segments, each
+/// with its own nonce, so damage or tampering anywhere in a file is
+/// detected. Names are encrypted deterministically, with a nonce derived
+/// from the name itself, so a file keeps the same stored name across runs.
+#[derive(Clone)]
+pub(crate) struct Cipher {
+    contents: XChaCha20Poly1305,
+    names: Option<NameCipher>,
+}
+
+#[derive(Clone)]
+struct NameCipher {
+    cipher: XChaCha20Poly1305,
+    nonce_key: Zeroizing<[u8; 32]>,
+}
+
+impl fmt::Debug for Cipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Cipher")
+            .field("encrypt_names", &self.names.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Cipher {
+    /// Unlocks the encrypted backup at `root` with `key`.
+    pub(crate) fn open(root: &Path, key: &EncryptionKey) -> io::Result<Cipher> {
+        let file = File::open(settings_path(root))?;
+        let settings: Settings = serde_json::from_reader(BufReader::new(file))?;
+        if settings.version != ENCRYPTION_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported encryption version {}", settings.version),
+            ));
+        }
+        let salt = data_encoding::HEXLOWER
+            .decode(settings.salt.as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        let master = derive_master_key(&key.secret()?, &salt, &settings)?;
+        if check_value(&master) != settings.check {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "wrong passphrase or key file",
+            ));
+        }
+        Ok(Cipher::new(&master, settings.encrypt_names))
+    }
+
+    /// Unlocks the target at `root`, setting it up for encryption first when
+    /// it is missing or empty. With `dry_run` new settings are not written.
+    ///
+    /// A target holding unencrypted files is refused, as is one whose name
+    /// encryption differs from `encrypt_names`.
+    pub(crate) fn open_or_create(
+        root: &Path,
+        key: &EncryptionKey,
+        encrypt_names: bool,
+        dry_run: bool,
+    ) -> io::Result<Cipher> {
+        if is_encrypted(root) {
+            let cipher = Self::open(root, key)?;
+            return match (cipher.names.is_some(), encrypt_names) {
+                (true, false) => Err(io::Error::other(
+                    "the target encrypts file names; back up with --encrypt-names",
+                )),
+                (false, true) => Err(io::Error::other(
+                    "the target does not encrypt file names; it cannot be enabled later",
+                )),
+                _ => Ok(cipher),
+            };
+        }
+        if root.is_dir() && fs::read_dir(root)?.next().is_some() {
+            return Err(io::Error::other(
+                "the directory is not empty and holds an unencrypted backup",
+            ));
+        }
+
+        let mut salt = [0u8; 16];
+        random_bytes(&mut salt)?;
+        let params = Params::default();
+        let mut settings = Settings {
+            version: ENCRYPTION_VERSION,
+            salt: data_encoding::HEXLOWER.encode(&salt),
+            memory_cost: params.m_cost(),
+            time_cost: params.t_cost(),
+            parallelism: params.p_cost(),
+            encrypt_names,
+            check: String::new(),
+        };
+        let master = derive_master_key(&key.secret()?, &salt, &settings)?;
+        settings.check = check_value(&master);
+
+        if !dry_run {
+            let path = settings_path(root);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(path, serde_json::to_vec_pretty(&settings)?)?;
+        }
+        Ok(Cipher::new(&master, encrypt_names))
+    }
+
+    fn new(master: &[u8; 32], encrypt_names: bool) -> Cipher {
+        let subkey = |context| Zeroizing::new(blake3::derive_key(context, master));
+        let contents =
+            XChaCha20Poly1305::new(subkey("srb 2026-10-17 file contents").as_ref().into());
+        let names = encrypt_names.then(|| NameCipher {
+            cipher: XChaCha20Poly1305::new(subkey("srb 2026-10-17 file names").as_ref().into()),
+            nonce_key: subkey("srb 2026-10-17 file name nonces"),
+        });
+        Cipher { contents, names }
+    }
+
+    /// Returns the path under which the file at `relative_path` is stored,
+    /// which is `relative_path` itself unless names are encrypted.
+    pub(crate) fn encrypt_path(&self, relative_path: &Path) -> io::Result<PathBuf> {
+        let Some(names) = &self.names else {
+            return Ok(relative_path.to_path_buf());
+        };
+        let mut path = PathBuf::new();
+        for component in relative_path.components() {
+            match component {
+                Component::Normal(name) => path.push(names.encrypt(name)?),
+                other => path.push(other),
+            }
+        }
+        Ok(path)
+    }
+
+    /// Reverses [`encrypt_path`](Self::encrypt_path).
+    pub(crate) fn decrypt_path(&self, stored_path: &Path) -> io::Result<PathBuf> {
+        let Some(names) = &self.names else {
+            return Ok(stored_path.to_path_buf());
+        };
+        let mut path = PathBuf::new();
+        for component in stored_path.components() {
+            match component {
+                Component::Normal(name) => path.push(names.decrypt(name)?),
+                other => path.push(other),
+            }
+        }
+        Ok(path)
+    }
+}
+
+impl NameCipher {
+    /// Encrypts one file name into lowercase base32, which survives
+    /// case-insensitive filesystems.
+    fn encrypt(&self, name: &OsStr) -> io::Result<String> {
+        let plaintext = name_bytes(name)?;
+        let hash = blake3::keyed_hash(&self.nonce_key, &plaintext);
+        let nonce = name_nonce(&hash.as_bytes()[..16]);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, plaintext.as_slice())
+            .map_err(|_| io::Error::other("cannot encrypt file name"))?;
+
+        let mut stored = hash.as_bytes()[..16].to_vec();
+        stored.extend_from_slice(&ciphertext);
+        let encoded = BASE32_DNSSEC.encode(&stored);
+        if encoded.len() > MAX_NAME_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidFilename,
+                format!("file name {:?} is too long to encrypt", name),
+            ));
+        }
+        Ok(encoded)
+    }
+
+    fn decrypt(&self, stored: &OsStr) -> io::Result<OsString> {
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{:?} is not an encrypted file name", stored),
+            )
+        };
+        let stored = stored
+            .to_str()
+            .and_then(|s| BASE32_DNSSEC.decode(s.as_bytes()).ok())
+            .filter(|bytes| bytes.len() > 16)
+            .ok_or_else(invalid)?;
+        let (nonce, ciphertext) = stored.split_at(16);
+        let plaintext = self
+            .cipher
+            .decrypt(&name_nonce(nonce), ciphertext)
+            .map_err(|_| invalid())?;
+        name_from_bytes(plaintext).ok_or_else(invalid)
+    }
+}
+
+fn name_nonce(prefix: &[u8]) -> XNonce {
+    let mut nonce = XNonce::default();
+    nonce[..prefix.len()].copy_from_slice(prefix);
+    nonce
+}
+
+#[cfg(unix)]
+fn name_bytes(name: &OsStr) -> io::Result<Vec<u8>> {
+    use std::os::unix::ffi::OsStrExt;
+    Ok(name.as_bytes().to_vec())
+}
+
+#[cfg(not(unix))]
+fn name_bytes(name: &OsStr) -> io::Result<Vec<u8>> {
+    match name.to_str() {
+        Some(name) => Ok(name.as_bytes().to_vec()),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidFilename,
+            format!("file name {:?} is not valid Unicode", name),
+        )),
+    }
+}
+
+#[cfg(unix)]
+fn name_from_bytes(bytes: Vec<u8>) -> Option<OsString> {
+    use std::os::unix::ffi::OsStringExt;
+    Some(OsString::from_vec(bytes))
+}
+
+#[cfg(not(unix))]
+fn name_from_bytes(bytes: Vec<u8>) -> Option<OsString> {
+    String::from_utf8(bytes).ok().map(OsString::from)
+}
+
+fn random_bytes(buf: &mut [u8]) -> io::Result<()> {
+    getrandom::getrandom(buf).map_err(|e| io::Error::other(e.to_string()))
+}
+
+fn derive_master_key(
+    secret: &[u8],
+    salt: &[u8],
+    settings: &Settings,
+) -> io::Result<Zeroizing<[u8; 32]>> {
+    let params = Params::new(
+        settings.memory_cost,
+        settings.time_cost,
+        settings.parallelism,
+        Some(32),
+    )
+    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    let mut master = Zeroizing::new([0u8; 32]);
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(secret, salt, master.as_mut())
+        .map_err(|e| io::Error::other(e.to_string()))?;
+    Ok(master)
+}
+
+fn check_value(master: &[u8; 32]) -> String {
+    data_encoding::HEXLOWER.encode(&blake3::derive_key("srb 2026-10-17 key check", master))
+}
+
+/// A writer encrypting into another writer, or passing data through
+/// unchanged when there is no cipher.
+pub(crate) struct Encrypter<W: Write> {
+    inner: W,
+    sealing: Option<Sealing>,
+}
+
+struct Sealing {
+    cipher: XChaCha20Poly1305,
+    nonce_prefix: [u8; NONCE_PREFIX_SIZE],
+    counter: u32,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> Encrypter<W> {
+    /// Starts writing to `inner`, with the header of an encrypted file when
+    /// `cipher` is given.
+    pub(crate) fn new(mut inner: W, cipher: Option<&Cipher>) -> io::Result<Self> {
+        let sealing = match cipher {
+            Some(cipher) => {
+                let mut nonce_prefix = [0u8; NONCE_PREFIX_SIZE];
+                random_bytes(&mut nonce_prefix)?;
+                inner.write_all(MAGIC)?;
+                inner.write_all(&[FORMAT_VERSION])?;
+                inner.write_all(&nonce_prefix)?;
+                Some(Sealing {
+                    cipher: cipher.contents.clone(),
+                    nonce_prefix,
+                    counter: 0,
+                    buffer: Vec::with_capacity(SEGMENT_SIZE + TAG_SIZE),
+                })
+            }
+            None => None,
+        };
+        Ok(Encrypter { inner, sealing })
+    }
+
+    /// Seals the last segment and returns the inner writer.
+    pub(crate) fn finish(mut self) -> io::Result<W> {
+        if let Some(sealing) = &mut self.sealing {
+            sealing.seal(&mut self.inner, true)?;
+        }
+        Ok(self.inner)
+    }
+}
+
+impl Sealing {
+    fn seal(&mut self, inner: &mut impl Write, last: bool) -> io::Result<()> {
+        let nonce = segment_nonce(&self.nonce_prefix, self.counter, last);
+        self.cipher
+            .encrypt_in_place(&nonce, b"", &mut self.buffer)
+            .map_err(|_| io::Error::other("encryption failed"))?;
+        inner.write_all(&self.buffer)?;
+        self.buffer.clear();
+        self.counter = self
+            .counter
+            .checked_add(1)
+            .ok_or_else(|| io::Error::other("file too large to encrypt"))?;
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for Encrypter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let Some(sealing) = &mut self.sealing else {
+            return self.inner.write(buf);
+        };
+        let len = buf.len().min(SEGMENT_SIZE - sealing.buffer.len());
+        sealing.buffer.extend_from_slice(&buf[..len]);
+        if sealing.buffer.len() == SEGMENT_SIZE {
+            sealing.seal(&mut self.inner, false)?;
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn segment_nonce(prefix: &[u8; NONCE_PREFIX_SIZE], counter: u32, last: bool) -> XNonce {
+    let mut nonce = XNonce::default();
+    nonce[..NONCE_PREFIX_SIZE].copy_from_slice(prefix);
+    nonce[NONCE_PREFIX_SIZE..NONCE_PREFIX_SIZE + 4].copy_from_slice(&counter.to_be_bytes());
+    nonce[NONCE_PREFIX_SIZE + 4] = u8::from(last);
+    nonce
+}
+
+/// A reader yielding the plaintext of an encrypted file.
+struct Decrypter<R: Read> {
+    inner: R,
+    cipher: XChaCha20Poly1305,
+    nonce_prefix: [u8; NONCE_PREFIX_SIZE],
+    counter: u32,
+    plaintext: Vec<u8>,
+    position: usize,
+    finished: bool,
+}
+
+impl<R: Read> Decrypter<R> {
+    fn new(mut inner: R, cipher: &Cipher) -> io::Result<Self> {
+        let mut header = [0u8; MAGIC.len() + 1 + NONCE_PREFIX_SIZE];
+        match inner.read_exact(&mut header) {
+            Ok(()) if header.starts_with(MAGIC) => {}
+            Ok(()) | Err(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "not an encrypted file",
+                ))
+            }
+        }
+        if header[MAGIC.len()] != FORMAT_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported encrypted file version {}", header[MAGIC.len()]),
+            ));
+        }
+        let mut nonce_prefix = [0u8; NONCE_PREFIX_SIZE];
+        nonce_prefix.copy_from_slice(&header[MAGIC.len() + 1..]);
+        Ok(Decrypter {
+            inner,
+            cipher: cipher.contents.clone(),
+            nonce_prefix,
+            counter: 0,
+            plaintext: Vec::with_capacity(SEGMENT_SIZE + TAG_SIZE),
+            position: 0,
+            finished: false,
+        })
+    }
+
+    /// Reads and opens the next segment into `plaintext`.
+    fn open_segment(&mut self) -> io::Result<()> {
+        self.plaintext.resize(SEGMENT_SIZE + TAG_SIZE, 0);
+        let mut len = 0;
+        while len < self.plaintext.len() {
+            match self.inner.read(&mut self.plaintext[len..]) {
+                Ok(0) => break,
+                Ok(n) => len += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        self.plaintext.truncate(len);
+
+        // Only the last segment is shorter than a full one
+        let last = len < SEGMENT_SIZE + TAG_SIZE;
+        let nonce = segment_nonce(&self.nonce_prefix, self.counter, last);
+        self.cipher
+            .decrypt_in_place(&nonce, b"", &mut self.plaintext)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "encrypted data is damaged"))?;
+        self.counter = self.counter.wrapping_add(1);
+        self.position = 0;
+        self.finished = last;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for Decrypter<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.plaintext.len() {
+            if self.finished {
+                return Ok(0);
+            }
+            self.open_segment()?;
+        }
+        let len = buf.len().min(self.plaintext.len() - self.position);
+        buf[..len].copy_from_slice(&self.plaintext[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+/// Returns a reader yielding the plaintext of data written by an
+/// [`Encrypter`] with `cipher`, or the data itself when there is no cipher.
+pub(crate) fn decrypter<'a, R: Read + 'a>(
+    reader: R,
+    cipher: Option<&Cipher>,
+) -> io::Result<Box<dyn Read + 'a>> {
+    Ok(match cipher {
+        Some(cipher) => Box::new(Decrypter::new(reader, cipher)?),
+        None => Box::new(reader),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cipher(key: u8) -> Cipher {
+        Cipher::new(&[key; 32], true)
+    }
+
+    fn encrypt(data: &[u8], cipher: &Cipher) -> Vec<u8> {
+        let mut encrypter = Encrypter::new(Vec::new(), Some(cipher)).unwrap();
+        encrypter.write_all(data).unwrap();
+        encrypter.finish().unwrap()
+    }
+
+    fn decrypt(data: &[u8], cipher: &Cipher) -> io::Result<Vec<u8>> {
+        let mut plaintext = Vec::new();
+        decrypter(data, Some(cipher))?.read_to_end(&mut plaintext)?;
+        Ok(plaintext)
+    }
+
+    fn sample(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn contents_round_trip_around_segment_sizes() {
+        let cipher = cipher(1);
+        for len in [0, 1, SEGMENT_SIZE, SEGMENT_SIZE + 1, 3 * SEGMENT_SIZE] {
+            let data = sample(len);
+            let encrypted = encrypt(&data, &cipher);
+            assert_eq!(decrypt(&encrypted, &cipher).unwrap(), data, "{} bytes", len);
+        }
+    }
+
+    #[test]
+    fn truncation_at_a_segment_boundary_is_detected() {
+        let cipher = cipher(1);
+        let header = MAGIC.len() + 1 + NONCE_PREFIX_SIZE;
+        for len in [SEGMENT_SIZE, 2 * SEGMENT_SIZE, 2 * SEGMENT_SIZE + 1] {
+            let encrypted = encrypt(&sample(len), &cipher);
+            let truncated = &encrypted[..header + SEGMENT_SIZE + TAG_SIZE];
+            assert!(decrypt(truncated, &cipher).is_err(), "{} bytes", len);
+        }
+        // Only the header left
+        let encrypted = encrypt(&sample(10), &cipher);
+        assert!(decrypt(&encrypted[..header], &cipher).is_err());
+    }
+
+    #[test]
+    fn damaged_contents_are_detected() {
+        let cipher = cipher(1);
+        let encrypted = encrypt(&sample(SEGMENT_SIZE + 100), &cipher);
+        for position in [MAGIC.len() + 1, 100, encrypted.len() - 1] {
+            let mut damaged = encrypted.clone();
+            damaged[position] ^= 1;
+            assert!(decrypt(&damaged, &cipher).is_err(), "byte {}", position);
+        }
+    }
+
+    #[test]
+    fn contents_do_not_open_with_another_key() {
+        let encrypted = encrypt(&sample(1000), &cipher(1));
+        assert!(decrypt(&encrypted, &cipher(2)).is_err());
+    }
+
+    #[test]
+    fn paths_round_trip() {
+        let cipher = cipher(1);
+        let path = Path::new("dir/sub dir/file.txt");
+        let stored = cipher.encrypt_path(path).unwrap();
+        assert_eq!(stored.components().count(), 3);
+        assert!(!stored.to_string_lossy().contains("file"));
+        assert_eq!(cipher.decrypt_path(&stored).unwrap(), path);
+        // Names are stored the same way on every run
+        assert_eq!(cipher.encrypt_path(path).unwrap(), stored);
+        assert!(self::cipher(2).decrypt_path(&stored).is_err());
+    }
+
+    #[test]
+    fn names_too_long_for_a_temporary_name_are_refused() {
+        let cipher = cipher(1);
+        let longest = (1..256)
+            .take_while(|&len| cipher.encrypt_path(Path::new(&"a".repeat(len))).is_ok())
+            .last()
+            .unwrap();
+        let stored = cipher
+            .encrypt_path(Path::new(&"a".repeat(longest)))
+            .unwrap();
+        assert!(stored.as_os_str().len() + TEMP_NAME_OVERHEAD <= 255);
+    }
+}
@@ -0,0 +1,93 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// Errors that stop a backup before any file is processed.
+///
+/// Failures affecting a single file do not abort the run; they are recorded
+/// in the [`BackupReport`](crate::BackupReport) instead.
+#[derive(Debug)]
+pub enum Error {
+    /// The source path is missing or is not a directory.
+    SourceNotDir(PathBuf),
+    /// A source of a multi-source backup has no name to use for its
+    /// subtree of the target, or shares it with another source.
+    SourceName(PathBuf, String),
+    /// The target path exists but is not a directory.
+    TargetNotDir(PathBuf),
+    /// The target directory could not be created.
+    CreateTarget(PathBuf, io::Error),
+    /// The snapshot directory could not be prepared.
+    Snapshot(PathBuf, io::Error),
+    /// No snapshot with the requested id exists.
+    SnapshotNotFound(String),
+    /// Pruning was requested without any rule selecting snapshots to keep.
+    EmptyRetentionPolicy,
+    /// Pruning was requested for a target that is neither a snapshot
+    /// target nor a repository.
+    NotSnapshotTarget(PathBuf),
+    /// An include or exclude glob could not be parsed.
+    Pattern(String, String),
+    /// The manifest of a backup is missing or could not be read.
+    Manifest(PathBuf, io::Error),
+    /// The chunk repository could not be opened or created.
+    Repository(PathBuf, io::Error),
+    /// An encrypted backup could not be unlocked or set up, for example
+    /// because the key is wrong.
+    Encryption(PathBuf, io::Error),
+    /// The config file could not be read or parsed.
+    Config(PathBuf, String),
+    /// No profile with the requested name is defined in the config file.
+    ProfileNotFound(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::SourceNotDir(_) => {
+                write!(f, "Source directory does not exist or is not a directory.")
+            }
+            Error::SourceName(path, e) => {
+                write!(f, "Cannot back up {:?} into its own subtree: {}", path, e)
+            }
+            Error::TargetNotDir(_) => write!(f, "Target path exists but is not a directory."),
+            Error::CreateTarget(_, e) => write!(f, "Failed to create target directory: {}", e),
+            Error::Snapshot(path, e) => write!(f, "Failed to prepare snapshot {:?}: {}", path, e),
+            Error::SnapshotNotFound(name) => write!(f, "Snapshot {:?} not found.", name),
+            Error::EmptyRetentionPolicy => {
+                write!(
+                    f,
+                    "No retention rule given; refusing to remove every snapshot."
+                )
+            }
+            Error::NotSnapshotTarget(path) => write!(
+                f,
+                "{:?} holds neither snapshots nor a repository; refusing to prune it.",
+                path
+            ),
+            Error::Pattern(pattern, e) => write!(f, "Invalid pattern {:?}: {}", pattern, e),
+            Error::Manifest(path, e) => write!(f, "Failed to read manifest {:?}: {}", path, e),
+            Error::Repository(path, e) => write!(f, "Failed to open repository {:?}: {}", path, e),
+            Error::Encryption(path, e) => {
+                write!(f, "Failed to unlock encrypted backup {:?}: {}", path, e)
+            }
+            Error::Config(path, e) => write!(f, "Failed to read config file {:?}: {}", path, e),
+            Error::ProfileNotFound(name) => write!(f, "Profile {:?} not found.", name),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::CreateTarget(_, e)
+            | Error::Snapshot(_, e)
+            | Error::Manifest(_, e)
+            | Error::Repository(_, e)
+            | Error::Encryption(_, e) => Some(e),
+            _ => None,
+        }
+    }
+}
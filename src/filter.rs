@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::gitignore::Gitignore;
+
+use crate::error::{Error, Result};
+use crate::manifest::is_manifest_dir;
+
+/// Name of the per-directory file listing gitignore-style exclude patterns.
+pub const IGNORE_FILE: &str = ".srbignore";
+
+/// A set of glob patterns matched against paths relative to the source.
+///
+/// Patterns without a `/` match the file name at any depth, like
+/// `node_modules` or `*.swp`; patterns containing a `/` match the whole
+/// relative path, like `build/*.o`.
+#[derive(Debug)]
+struct GlobList {
+    names: GlobSet,
+    paths: GlobSet,
+}
+
+impl GlobList {
+    fn new(patterns: &[String]) -> Result<Self> {
+        let mut names = GlobSetBuilder::new();
+        let mut paths = GlobSetBuilder::new();
+        for pattern in patterns {
+            let trimmed = pattern.trim_start_matches('/');
+            let glob =
+                Glob::new(trimmed).map_err(|e| Error::Pattern(pattern.clone(), e.to_string()))?;
+            if pattern.contains('/') {
+                paths.add(glob);
+            } else {
+                names.add(glob);
+            }
+        }
+        let build = |builder: GlobSetBuilder| {
+            builder
+                .build()
+                .map_err(|e| Error::Pattern(patterns.join(" "), e.to_string()))
+        };
+        Ok(GlobList {
+            names: build(names)?,
+            paths: build(paths)?,
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.names.is_empty() && self.paths.is_empty()
+    }
+
+    fn is_match(&self, relative_path: &Path) -> bool {
+        relative_path
+            .file_name()
+            .is_some_and(|name| self.names.is_match(name))
+            || self.paths.is_match(relative_path)
+    }
+}
+
+/// Decides which entries of the source tree take part in a backup.
+///
+/// Combines `--include`/`--exclude` globs with the `.srbignore` files found
+/// in the source, which are loaded lazily as directories are visited.
+#[derive(Debug)]
+pub(crate) struct Filter {
+    source_dir: PathBuf,
+    include: GlobList,
+    exclude: GlobList,
+    ignore_files: HashMap<PathBuf, Option<Gitignore>>,
+    read_ignore_files: bool,
+    /// Problems found while reading `.srbignore` files.
+    pub(crate) warnings: Vec<String>,
+}
+
+impl Filter {
+    pub(crate) fn new(source_dir: &Path, include: &[String], exclude: &[String]) -> Result<Self> {
+        Ok(Filter {
+            source_dir: source_dir.to_path_buf(),
+            include: GlobList::new(include)?,
+            exclude: GlobList::new(exclude)?,
+            ignore_files: HashMap::new(),
+            read_ignore_files: true,
+            warnings: Vec::new(),
+        })
+    }
+
+    /// Only applies the glob patterns, ignoring `.srbignore` files.
+    pub(crate) fn without_ignore_files(mut self) -> Self {
+        self.read_ignore_files = false;
+        self
+    }
+
+    /// Returns whether the file at `relative_path` should be left out,
+    /// checking each of its parent directories as a walk would.
+    pub(crate) fn is_file_excluded(&mut self, relative_path: &Path) -> bool {
+        let mut parents: Vec<&Path> = relative_path.ancestors().skip(1).collect();
+        parents.reverse();
+        parents.into_iter().any(|dir| self.is_excluded(dir, true))
+            || self.is_excluded(relative_path, false)
+    }
+
+    /// Returns whether the entry at `relative_path` should be left out.
+    ///
+    /// Include patterns only apply to files, so that directories holding
+    /// included files are still visited. The `.srb` bookkeeping directory
+    /// at the root is always left out.
+    pub(crate) fn is_excluded(&mut self, relative_path: &Path, is_dir: bool) -> bool {
+        if relative_path.as_os_str().is_empty() {
+            return false;
+        }
+        if is_manifest_dir(relative_path) {
+            return true;
+        }
+
+        if self.exclude.is_match(relative_path)
+            || (self.read_ignore_files && self.is_ignored(relative_path, is_dir))
+        {
+            return true;
+        }
+
+        !is_dir && !self.include.is_empty() && !self.include.is_match(relative_path)
+    }
+
+    /// Checks the `.srbignore` files of every ancestor directory, the
+    /// deepest one first so that it can override its parents.
+    fn is_ignored(&mut self, relative_path: &Path, is_dir: bool) -> bool {
+        let path = self.source_dir.join(relative_path);
+        for dir in relative_path.ancestors().skip(1) {
+            if let Some(ignore) = self.ignore_file(dir) {
+                let matched = ignore.matched(&path, is_dir);
+                if matched.is_ignore() {
+                    return true;
+                }
+                if matched.is_whitelist() {
+                    return false;
+                }
+            }
+        }
+        false
+    }
+
+    fn ignore_file(&mut self, relative_dir: &Path) -> Option<&Gitignore> {
+        if !self.ignore_files.contains_key(relative_dir) {
+            let path = self.source_dir.join(relative_dir).join(IGNORE_FILE);
+            let ignore = if path.is_file() {
+                let (ignore, error) = Gitignore::new(&path);
+                if let Some(e) = error {
+                    self.warnings
+                        .push(format!("Error reading {:?}: {}", path, e));
+                }
+                Some(ignore)
+            } else {
+                None
+            };
+            self.ignore_files.insert(relative_dir.to_path_buf(), ignore);
+        }
+        self.ignore_files[relative_dir].as_ref()
+    }
+}
@@ -0,0 +1,1534 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, FileType};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::SystemTime;
+
+use indicatif::MultiProgress;
+use walkdir::WalkDir;
+
+use crate::compare::{hash_file, needs_copy, needs_copy_from_manifest, CompareMode};
+use crate::compress::{is_incompressible, Compression};
+use crate::copy::{copy_with_progress, progress_bars, replace_with, Transform};
+use crate::crypto::{is_encrypted, Cipher, EncryptionKey};
+use crate::error::{Error, Result};
+use crate::filter::Filter;
+use crate::manifest::{is_manifest_dir, key, EntryKind, Manifest, ManifestEntry};
+use crate::metadata::{make_writable, Preserve};
+use crate::mirror::delete_extraneous;
+use crate::report::{BackupReport, FileAction, FileReport};
+use crate::repository::Repository;
+use crate::snapshot::{latest_snapshot, new_snapshot_name, update_latest, LATEST_LINK};
+use crate::special::{can_create, device_number, hard_link_id, make_node, special_kind};
+use crate::symlink::{points_outside, replace_with_symlink, SymlinkMode};
+use crate::xattrs::{apply_attributes, read_xattrs, supports_xattrs};
+
+/// A differential backup from one or more source directories to a target
+/// directory.
+///
+/// Files that are missing from the target, or that differ from their
+/// target copy according to the [`CompareMode`], are copied; everything
+/// else is left untouched.
+#[derive(Debug, Clone)]
+pub struct BackupJob {
+    sources: Vec<PathBuf>,
+    target: PathBuf,
+    progress: bool,
+    dry_run: bool,
+    compare: CompareMode,
+    preserve: Preserve,
+    include: Vec<String>,
+    exclude: Vec<String>,
+    delete: bool,
+    delete_dry_run: bool,
+    max_delete_percent: u8,
+    jobs: usize,
+    snapshot: bool,
+    repository: bool,
+    compression: Compression,
+    compress_level: Option<i32>,
+    key: Option<EncryptionKey>,
+    encrypt_names: bool,
+    symlinks: SymlinkMode,
+    safe_links: bool,
+}
+
+/// A source directory of a run.
+#[derive(Debug)]
+pub(crate) struct Source {
+    pub(crate) dir: PathBuf,
+    /// Path inside the target that the directory is backed up into; empty
+    /// when the job has a single source.
+    pub(crate) subtree: PathBuf,
+    pub(crate) filter: Filter,
+}
+
+impl Source {
+    /// Returns the path inside the target of `relative_path`, a path inside
+    /// the source directory.
+    pub(crate) fn target_path(&self, relative_path: &Path) -> PathBuf {
+        join_subtree(&self.subtree, relative_path)
+    }
+}
+
+fn join_subtree(subtree: &Path, relative_path: &Path) -> PathBuf {
+    // Joining an empty path would leave a trailing separator
+    if relative_path.as_os_str().is_empty() {
+        subtree.to_path_buf()
+    } else {
+        subtree.join(relative_path)
+    }
+}
+
+/// Checks the source directories and chooses the subtree of the target
+/// each one is backed up into, or is checked against by a verify.
+pub(crate) fn prepare_sources(
+    dirs: &[PathBuf],
+    include: &[String],
+    exclude: &[String],
+) -> Result<Vec<Source>> {
+    let mut names = HashSet::new();
+    let mut sources = Vec::with_capacity(dirs.len());
+    for dir in dirs {
+        if !dir.is_dir() {
+            return Err(Error::SourceNotDir(dir.clone()));
+        }
+
+        let subtree = if dirs.len() == 1 {
+            PathBuf::new()
+        } else {
+            // Paths like `.` only have a name once resolved
+            let name = match dir.file_name() {
+                Some(name) => Some(name.to_os_string()),
+                None => fs::canonicalize(dir)
+                    .ok()
+                    .and_then(|dir| dir.file_name().map(|name| name.to_os_string())),
+            };
+            let Some(name) = name else {
+                return Err(Error::SourceName(
+                    dir.clone(),
+                    "the path has no last component to name the subtree".to_string(),
+                ));
+            };
+            if is_manifest_dir(Path::new(&name)) {
+                return Err(Error::SourceName(
+                    dir.clone(),
+                    format!("{:?} is reserved for the manifest", name),
+                ));
+            }
+            if !names.insert(name.clone()) {
+                return Err(Error::SourceName(
+                    dir.clone(),
+                    format!("another source is also named {:?}", name),
+                ));
+            }
+            PathBuf::from(name)
+        };
+
+        sources.push(Source {
+            dir: dir.clone(),
+            subtree,
+            filter: Filter::new(dir, include, exclude)?,
+        });
+    }
+    Ok(sources)
+}
+
+/// Where a run writes its files, and what it compares them against.
+#[derive(Debug)]
+struct Destination {
+    /// Directory receiving copied files.
+    dir: PathBuf,
+    /// Directory holding the previous version of each file. Unchanged files
+    /// are hard-linked from here when it differs from `dir`.
+    base: Option<PathBuf>,
+    /// Manifest of `base`, or index of the previous repository snapshot,
+    /// used instead of examining the stored files.
+    manifest: Option<Manifest>,
+    /// Chunk store receiving the file contents instead of `dir`.
+    repository: Option<Repository>,
+    /// Keys of an encrypted target.
+    cipher: Option<Cipher>,
+    /// Attributes applied to the copies in `dir`.
+    preserve: Preserve,
+}
+
+impl BackupJob {
+    /// Creates a job backing up `source` into `target`.
+    pub fn new(source: impl Into<PathBuf>, target: impl Into<PathBuf>) -> Self {
+        BackupJob {
+            sources: vec![source.into()],
+            target: target.into(),
+            progress: false,
+            dry_run: false,
+            compare: CompareMode::default(),
+            preserve: Preserve::default(),
+            include: Vec::new(),
+            exclude: Vec::new(),
+            delete: false,
+            delete_dry_run: false,
+            max_delete_percent: 50,
+            jobs: 1,
+            snapshot: false,
+            repository: false,
+            compression: Compression::None,
+            compress_level: None,
+            key: None,
+            encrypt_names: false,
+            symlinks: SymlinkMode::default(),
+            safe_links: false,
+        }
+    }
+
+    /// Adds another directory to back up.
+    ///
+    /// With several sources, each one is backed up into a subtree of the
+    /// target named after its last path component, so `/etc` and `/srv`
+    /// end up in `etc` and `srv`. The names must differ. All sources share
+    /// one progress display and one report, whose paths include the
+    /// subtree.
+    pub fn add_source(mut self, source: impl Into<PathBuf>) -> Self {
+        self.sources.push(source.into());
+        self
+    }
+
+    /// Draws progress bars on the terminal while the job runs.
+    ///
+    /// Disabled by default.
+    pub fn progress(mut self, enabled: bool) -> Self {
+        self.progress = enabled;
+        self
+    }
+
+    /// Scans and compares both trees and reports the planned action for
+    /// every file, without creating directories or copying anything.
+    ///
+    /// Also previews mirror mode deletions.
+    pub fn dry_run(mut self, enabled: bool) -> Self {
+        self.dry_run = enabled;
+        self
+    }
+
+    /// Sets how existing target files are compared against the source.
+    /// Defaults to [`CompareMode::Mtime`].
+    pub fn compare(mut self, mode: CompareMode) -> Self {
+        self.compare = mode;
+        self
+    }
+
+    /// Selects which source attributes are applied to copied files.
+    /// Nothing is preserved by default.
+    pub fn preserve(mut self, preserve: Preserve) -> Self {
+        self.preserve = preserve;
+        self
+    }
+
+    /// Adds a glob pattern of files to back up. When any include pattern
+    /// is set, files matching none of them are skipped.
+    ///
+    /// Patterns without a `/` match file names at any depth; others match
+    /// the path relative to each source directory.
+    pub fn include(mut self, pattern: impl Into<String>) -> Self {
+        self.include.push(pattern.into());
+        self
+    }
+
+    /// Adds a glob pattern of files and directories to leave out.
+    ///
+    /// Excluded directories are not descended into. Patterns are matched
+    /// like [`include`](Self::include) patterns, and `.srbignore` files in
+    /// the source add gitignore-style excludes for their directory.
+    pub fn exclude(mut self, pattern: impl Into<String>) -> Self {
+        self.exclude.push(pattern.into());
+        self
+    }
+
+    /// Mirrors the source: after copying, removes files and empty
+    /// directories from the target that no longer exist in the source.
+    pub fn delete(mut self, enabled: bool) -> Self {
+        self.delete = enabled;
+        self
+    }
+
+    /// Reports what mirror mode would delete without removing anything.
+    pub fn delete_dry_run(mut self, enabled: bool) -> Self {
+        self.delete_dry_run = enabled;
+        self
+    }
+
+    /// Largest share of the target's files, in percent, that mirror mode is
+    /// allowed to delete in one run. Defaults to 50.
+    pub fn max_delete_percent(mut self, percent: u8) -> Self {
+        self.max_delete_percent = percent.min(100);
+        self
+    }
+
+    /// Number of files copied concurrently. Defaults to 1.
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs.max(1);
+        self
+    }
+
+    /// Writes each run into a new timestamped snapshot directory inside the
+    /// target, such as `2026-10-17T02-00-00`, instead of updating a single
+    /// copy.
+    ///
+    /// Files unchanged since the previous snapshot are hard-linked to it, and
+    /// a `latest` symlink is pointed at the new snapshot. Mirror mode does
+    /// not apply to snapshots.
+    pub fn snapshot(mut self, enabled: bool) -> Self {
+        self.snapshot = enabled;
+        self
+    }
+
+    /// Stores the backup in a deduplicating chunk repository instead of as
+    /// plain files.
+    ///
+    /// Files are split into content-defined chunks, each distinct chunk is
+    /// stored once, and every run records a new snapshot index listing the
+    /// chunks of each file. A large file with a small change only adds the
+    /// chunks around the change. The target must be empty or already hold a
+    /// repository. Mirror mode does not apply.
+    pub fn repository(mut self, enabled: bool) -> Self {
+        self.repository = enabled;
+        self
+    }
+
+    /// Compresses copied files. The compression used for each file is
+    /// recorded in the manifest, and restore and verify undo it
+    /// transparently; file names stay the same.
+    ///
+    /// Files that are compressed already, judged by their extension or by
+    /// sampling their first bytes, are stored as they are. Not applied in
+    /// repository mode.
+    pub fn compress(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Sets the compression level. Defaults to
+    /// [`Compression::default_level`].
+    pub fn compress_level(mut self, level: i32) -> Self {
+        self.compress_level = Some(level);
+        self
+    }
+
+    /// Encrypts the backup with a key derived from `key` using Argon2id.
+    ///
+    /// File contents and the manifest are sealed with XChaCha20-Poly1305.
+    /// The key derivation settings are stored in the target the first time,
+    /// and later runs, restores and verifies must use the same passphrase
+    /// or key file. An unencrypted target cannot be switched to encryption,
+    /// and repositories cannot be encrypted.
+    pub fn encrypt(mut self, key: EncryptionKey) -> Self {
+        self.key = Some(key);
+        self
+    }
+
+    /// Also encrypts file and directory names when encrypting. Like the
+    /// key, this is fixed when the target is first encrypted.
+    pub fn encrypt_names(mut self, enabled: bool) -> Self {
+        self.encrypt_names = enabled;
+        self
+    }
+
+    /// Sets what happens to symbolic links in the source. Defaults to
+    /// [`SymlinkMode::Preserve`].
+    ///
+    /// Preserved links are recorded in the manifest and recreated in the
+    /// target; in an encrypted target or a repository they are only
+    /// recorded. Followed links are backed up as the files and directories
+    /// they point to, and links leading back to one of their own parent
+    /// directories are reported instead of being followed.
+    pub fn symlinks(mut self, mode: SymlinkMode) -> Self {
+        self.symlinks = mode;
+        self
+    }
+
+    /// Refuses symbolic links that point outside their source directory,
+    /// reporting them as failed. A preserved link is judged by its text,
+    /// so absolute links are always refused; a followed link by where it
+    /// resolves to.
+    pub fn safe_links(mut self, enabled: bool) -> Self {
+        self.safe_links = enabled;
+        self
+    }
+
+    /// The first source directory, the only one unless others were added
+    /// with [`add_source`](Self::add_source).
+    pub fn source(&self) -> &Path {
+        &self.sources[0]
+    }
+
+    pub fn sources(&self) -> &[PathBuf] {
+        &self.sources
+    }
+
+    pub fn target(&self) -> &Path {
+        &self.target
+    }
+
+    /// Runs the backup.
+    ///
+    /// Returns an error only if the source or target directory is unusable;
+    /// failures on individual files are recorded in the report.
+    pub fn run(&self) -> Result<BackupReport> {
+        let started = SystemTime::now();
+        let target_dir = self.target.as_path();
+
+        let mut sources = prepare_sources(&self.sources, &self.include, &self.exclude)?;
+
+        // Validate and prepare the target directory
+        if target_dir.exists() {
+            if !target_dir.is_dir() {
+                return Err(Error::TargetNotDir(self.target.clone()));
+            }
+        } else if !self.dry_run {
+            // Attempt to create the target directory
+            fs::create_dir_all(target_dir)
+                .map_err(|e| Error::CreateTarget(self.target.clone(), e))?;
+        }
+
+        let mut report = BackupReport::new(&self.sources[0], target_dir);
+        report.sources = self.sources.clone();
+        report.started = started;
+        report.dry_run = self.dry_run;
+
+        if !self.repository && Repository::is_repository(target_dir) {
+            return Err(Error::Repository(
+                self.target.clone(),
+                io::Error::other("the target is a repository; back up with --repository"),
+            ));
+        }
+
+        let cipher = match &self.key {
+            Some(_) if self.repository => {
+                return Err(Error::Encryption(
+                    self.target.clone(),
+                    io::Error::new(
+                        io::ErrorKind::Unsupported,
+                        "repositories cannot be encrypted",
+                    ),
+                ))
+            }
+            Some(key) => Some(
+                Cipher::open_or_create(target_dir, key, self.encrypt_names, self.dry_run)
+                    .map_err(|e| Error::Encryption(self.target.clone(), e))?,
+            ),
+            None if is_encrypted(target_dir) => {
+                return Err(Error::Encryption(
+                    self.target.clone(),
+                    io::Error::new(
+                        io::ErrorKind::PermissionDenied,
+                        "the target is encrypted; a passphrase or key file is needed",
+                    ),
+                ))
+            }
+            None => None,
+        };
+
+        let mut destination = if self.repository {
+            let repository = Repository::open_or_create(target_dir, self.dry_run)
+                .map_err(|e| Error::Repository(self.target.clone(), e))?;
+            let previous = if target_dir.is_dir() {
+                latest_snapshot(target_dir).map_err(|e| Error::Snapshot(self.target.clone(), e))?
+            } else {
+                None
+            };
+            let manifest = match previous {
+                Some(snapshot) => Some(
+                    Manifest::read(&snapshot.path, None)
+                        .map_err(|e| Error::Snapshot(snapshot.path.clone(), e))?,
+                ),
+                None => None,
+            };
+            let name = new_snapshot_name();
+            if repository.index_path(&name).exists() {
+                return Err(Error::Snapshot(
+                    repository.index_path(&name),
+                    io::Error::new(io::ErrorKind::AlreadyExists, "snapshot already exists"),
+                ));
+            }
+            report.snapshot = Some(name);
+            Destination {
+                dir: target_dir.to_path_buf(),
+                base: None,
+                manifest,
+                repository: Some(repository),
+                cipher: None,
+                preserve: self.preserve,
+            }
+        } else if self.snapshot {
+            let previous = if target_dir.is_dir() {
+                latest_snapshot(target_dir).map_err(|e| Error::Snapshot(self.target.clone(), e))?
+            } else {
+                None
+            };
+            let name = new_snapshot_name();
+            let dir = target_dir.join(&name);
+            if dir.exists() {
+                return Err(Error::Snapshot(
+                    dir,
+                    io::Error::new(io::ErrorKind::AlreadyExists, "snapshot already exists"),
+                ));
+            }
+            if !self.dry_run {
+                fs::create_dir(&dir).map_err(|e| Error::Snapshot(dir.clone(), e))?;
+            }
+            report.snapshot = Some(name);
+            Destination {
+                dir,
+                base: previous.map(|s| s.path),
+                manifest: None,
+                repository: None,
+                cipher,
+                preserve: self.preserve,
+            }
+        } else {
+            Destination {
+                dir: target_dir.to_path_buf(),
+                base: Some(target_dir.to_path_buf()),
+                manifest: None,
+                repository: None,
+                cipher,
+                preserve: self.preserve,
+            }
+        };
+        // Extended attributes would be readable on an encrypted target, and
+        // some file systems cannot hold them; the manifest records them
+        let unsupported = (destination.preserve.xattrs || destination.preserve.acls)
+            && destination.cipher.is_none()
+            && !self.dry_run
+            && !supports_xattrs(&destination.dir);
+        if unsupported {
+            report.errors.push(format!(
+                "{:?} does not support extended attributes; they are only recorded in the manifest",
+                destination.dir
+            ));
+        }
+        if destination.cipher.is_some() || unsupported {
+            destination.preserve.xattrs = false;
+            destination.preserve.acls = false;
+        }
+        if let Some(base) = &destination.base {
+            match Manifest::load_with(base, destination.cipher.as_ref()) {
+                Ok(manifest) => destination.manifest = manifest,
+                Err(e) => report.errors.push(format!(
+                    "Error reading manifest {:?}, comparing with the target files instead: {}",
+                    Manifest::path(base),
+                    e
+                )),
+            }
+        }
+
+        // Collect all files, links and directories to process, together
+        // with the index of their source. Later names of a file with several
+        // hard links are set aside with the index of its first name.
+        let mut files_to_process = Vec::new();
+        let mut dirs_to_process = Vec::new();
+        let mut links_to_process = Vec::new();
+        let mut first_names: HashMap<(u64, u64), usize> = HashMap::new();
+        let follow = self.symlinks == SymlinkMode::Follow;
+        for (index, source) in sources.iter_mut().enumerate() {
+            let source_dir = source.dir.as_path();
+            let filter = &mut source.filter;
+            // Followed links must resolve inside the source with safe links
+            let inside = match (follow && self.safe_links, fs::canonicalize(source_dir)) {
+                (true, Ok(dir)) => Some(dir),
+                _ => None,
+            };
+            let mut refused = Vec::new();
+            let walker = WalkDir::new(source_dir)
+                .follow_links(follow)
+                .into_iter()
+                .filter_entry(|entry| {
+                    let relative_path = entry
+                        .path()
+                        .strip_prefix(source_dir)
+                        .unwrap_or(entry.path());
+                    if filter.is_excluded(relative_path, entry.file_type().is_dir()) {
+                        return false;
+                    }
+                    match &inside {
+                        Some(dir) if entry.path_is_symlink() => {
+                            let resolved = fs::canonicalize(entry.path());
+                            if resolved.is_ok_and(|path| path.starts_with(dir)) {
+                                true
+                            } else {
+                                refused.push(relative_path.to_path_buf());
+                                false
+                            }
+                        }
+                        _ => true,
+                    }
+                });
+            for entry in walker {
+                let entry = match entry {
+                    Ok(e) => e,
+                    Err(e) => {
+                        let path = e.path().unwrap_or(source_dir);
+                        let error = match e.loop_ancestor() {
+                            Some(ancestor) => format!(
+                                "Not following link back to its parent directory {:?}",
+                                ancestor
+                            ),
+                            None => format!("Error reading entry: {}", e),
+                        };
+                        // The filter still borrows the source
+                        report.files.push(FileReport::failed(
+                            join_subtree(
+                                &source.subtree,
+                                path.strip_prefix(source_dir).unwrap_or(path),
+                            ),
+                            error,
+                        ));
+                        continue;
+                    }
+                };
+
+                // Links only show up as links when they are not followed
+                let file_type = entry.file_type();
+                if file_type.is_file() {
+                    let id = entry.metadata().ok().as_ref().and_then(hard_link_id);
+                    match id.map(|id| first_names.get(&id).copied().ok_or(id)) {
+                        Some(Ok(first)) => links_to_process.push((index, entry, first)),
+                        Some(Err(id)) => {
+                            first_names.insert(id, files_to_process.len());
+                            files_to_process.push((index, entry));
+                        }
+                        None => files_to_process.push((index, entry)),
+                    }
+                } else if (file_type.is_symlink() && self.symlinks == SymlinkMode::Preserve)
+                    || special_kind(file_type).is_some()
+                {
+                    files_to_process.push((index, entry));
+                } else if file_type.is_dir()
+                    && (entry.depth() > 0 || !source.subtree.as_os_str().is_empty())
+                {
+                    // The target itself stands for a single source's root
+                    dirs_to_process.push((index, entry));
+                }
+            }
+            for relative_path in refused {
+                report.files.push(FileReport::failed(
+                    source.target_path(&relative_path),
+                    "Refusing to follow link outside the source",
+                ));
+            }
+        }
+
+        // Directories are created first, parents before children, so that
+        // empty ones exist in the target too
+        let first_dir = report.files.len();
+        let mut manifest_dirs = Vec::new();
+        for (source, entry) in &dirs_to_process {
+            let (file, recorded) = self.process_dir(entry.path(), &sources[*source], &destination);
+            if let Some(recorded) = recorded {
+                manifest_dirs.push((file.path.clone(), recorded));
+            }
+            report.files.push(file);
+        }
+
+        let (mp, pb) = progress_bars(
+            self.progress,
+            (files_to_process.len() + links_to_process.len()) as u64,
+        );
+
+        // Process files, handing them out to the workers in scan order
+        let next = AtomicUsize::new(0);
+        let workers = self.jobs.min(files_to_process.len()).max(1);
+        let mut processed: Vec<(usize, FileReport, Option<ManifestEntry>)> =
+            thread::scope(|scope| {
+                let handles: Vec<_> = (0..workers)
+                    .map(|_| {
+                        scope.spawn(|| {
+                            let mut done = Vec::new();
+                            loop {
+                                let index = next.fetch_add(1, Ordering::Relaxed);
+                                let Some((source, entry)) = files_to_process.get(index) else {
+                                    break;
+                                };
+                                let (file, recorded) = self.process_file(
+                                    entry.path(),
+                                    entry.file_type(),
+                                    &sources[*source],
+                                    &destination,
+                                    &mp,
+                                );
+                                done.push((index, file, recorded));
+                                pb.inc(1);
+                            }
+                            done
+                        })
+                    })
+                    .collect();
+                handles
+                    .into_iter()
+                    .flat_map(|handle| handle.join().expect("Backup worker panicked"))
+                    .collect()
+            });
+        processed.sort_by_key(|(index, _, _)| *index);
+
+        // Hard links are made once the copy of the first name exists; if it
+        // could not be made, the file is copied under each name instead
+        let mut linked = Vec::new();
+        for (source, entry, first) in &links_to_process {
+            let (_, first_file, first_recorded) = &processed[*first];
+            linked.push(if first_file.is_failed() {
+                self.process_file(
+                    entry.path(),
+                    entry.file_type(),
+                    &sources[*source],
+                    &destination,
+                    &mp,
+                )
+            } else {
+                self.process_hard_link(
+                    entry.path(),
+                    &sources[*source],
+                    first_file,
+                    first_recorded.as_ref(),
+                    &destination,
+                )
+            });
+            pb.inc(1);
+        }
+
+        // A plain target keeps the entries of files this run did not touch,
+        // such as files that failed to copy or are no longer in the source;
+        // a snapshot only holds what was written into it.
+        let mut manifest = match (&destination.manifest, self.snapshot || self.repository) {
+            (Some(previous), false) => previous.clone(),
+            _ => Manifest::new(),
+        };
+        for (path, recorded) in manifest_dirs {
+            manifest.insert(&path, recorded);
+        }
+        let processed = processed
+            .into_iter()
+            .map(|(_, file, recorded)| (file, recorded));
+        for (file, recorded) in processed.chain(linked) {
+            if let Some(entry) = recorded {
+                manifest.insert(&file.path, entry);
+            }
+            report.files.push(file);
+        }
+
+        if self.delete && !self.snapshot && !self.repository && target_dir.is_dir() {
+            pb.set_message("Removing deleted files...");
+            for source in &mut sources {
+                delete_extraneous(
+                    source,
+                    target_dir,
+                    destination.cipher.as_ref(),
+                    self.max_delete_percent,
+                    self.dry_run || self.delete_dry_run,
+                    &mut report,
+                );
+            }
+        }
+
+        // Writing and removing entries changes a directory's times, so
+        // attributes are applied once all of that is done, deepest first
+        if !self.dry_run && destination.repository.is_none() && !destination.preserve.is_empty() {
+            pb.set_message("Setting directory attributes...");
+            let dir_files = &mut report.files[first_dir..first_dir + dirs_to_process.len()];
+            for (file, (_, entry)) in dir_files.iter_mut().zip(&dirs_to_process).rev() {
+                if file.is_failed() {
+                    continue;
+                }
+                let result = stored_path(&file.path, &destination).and_then(|stored_path| {
+                    let metadata = entry.metadata().map_err(io::Error::from)?;
+                    let xattrs = read_xattrs(entry.path(), destination.preserve)?;
+                    apply_attributes(
+                        &metadata,
+                        &xattrs,
+                        &destination.dir.join(stored_path),
+                        destination.preserve,
+                    )
+                });
+                if let Err(e) = result {
+                    file.error = Some(format!("Error setting directory attributes: {}", e));
+                }
+            }
+        }
+
+        for source in &mut sources {
+            report.errors.append(&mut source.filter.warnings);
+        }
+
+        if !self.dry_run {
+            // Deletions only previewed are reported as `WouldDelete`
+            for file in &report.files {
+                if file.action == FileAction::Deleted && !file.is_failed() {
+                    manifest.remove(&file.path);
+                }
+            }
+            let result = match (&destination.repository, &report.snapshot) {
+                (Some(repository), Some(name)) => {
+                    manifest.write(&repository.index_path(name), None)
+                }
+                _ => manifest.save_with(&destination.dir, destination.cipher.as_ref()),
+            };
+            if let Err(e) = result {
+                report.errors.push(format!("Error writing manifest: {}", e));
+            }
+        }
+
+        if let (Some(name), false, false) = (&report.snapshot, self.dry_run, self.repository) {
+            if let Err(e) = update_latest(target_dir, name) {
+                report
+                    .errors
+                    .push(format!("Error updating the {} link: {}", LATEST_LINK, e));
+            }
+        }
+
+        pb.finish_with_message("Backup completed.");
+
+        report.finished = SystemTime::now();
+        Ok(report)
+    }
+
+    /// Creates the target copy of one directory found by the scan,
+    /// returning its manifest entry.
+    fn process_dir(
+        &self,
+        path: &Path,
+        source: &Source,
+        destination: &Destination,
+    ) -> (FileReport, Option<ManifestEntry>) {
+        match path.strip_prefix(&source.dir) {
+            Ok(relative_path) => {
+                backup_dir(path, &source.target_path(relative_path), destination, self)
+            }
+            Err(e) => (
+                FileReport::failed(path, format!("Error computing relative path: {}", e)),
+                None,
+            ),
+        }
+    }
+
+    /// Backs up a later name of a hard-linked file, whose first name was
+    /// backed up as `first`.
+    fn process_hard_link(
+        &self,
+        path: &Path,
+        source: &Source,
+        first: &FileReport,
+        first_recorded: Option<&ManifestEntry>,
+        destination: &Destination,
+    ) -> (FileReport, Option<ManifestEntry>) {
+        match path.strip_prefix(&source.dir) {
+            Ok(relative_path) => backup_hard_link(
+                &source.target_path(relative_path),
+                first,
+                first_recorded,
+                destination,
+                self,
+            ),
+            Err(e) => (
+                FileReport::failed(path, format!("Error computing relative path: {}", e)),
+                None,
+            ),
+        }
+    }
+
+    /// Backs up one file, link or special file found by the scan, returning
+    /// its manifest entry when the backup holds a copy of it.
+    fn process_file(
+        &self,
+        path: &Path,
+        file_type: FileType,
+        source: &Source,
+        destination: &Destination,
+        mp: &MultiProgress,
+    ) -> (FileReport, Option<ManifestEntry>) {
+        // Compute the path inside the target from the source directory
+        match path.strip_prefix(&source.dir) {
+            Ok(source_path) => {
+                let relative_path = source.target_path(source_path);
+                if file_type.is_symlink() {
+                    return backup_symlink(path, &relative_path, source_path, destination, self);
+                }
+                if let Some(kind) = special_kind(file_type) {
+                    return backup_special(path, &relative_path, kind, destination, self);
+                }
+                match &destination.repository {
+                    Some(repository) => {
+                        store_file(path, &relative_path, destination, repository, self, mp)
+                    }
+                    None => backup_file(path, &relative_path, destination, self, mp),
+                }
+            }
+            Err(e) => (
+                FileReport::failed(path, format!("Error computing relative path: {}", e)),
+                None,
+            ),
+        }
+    }
+}
+
+/// Returns the path inside the destination holding the copy of
+/// `relative_path`, which differs when the target encrypts names.
+fn stored_path(relative_path: &Path, destination: &Destination) -> io::Result<PathBuf> {
+    match &destination.cipher {
+        Some(cipher) => cipher.encrypt_path(relative_path),
+        None => Ok(relative_path.to_path_buf()),
+    }
+}
+
+/// Creates a source directory in the target if it is missing there.
+///
+/// Directories are recorded in the manifest so that empty ones can be
+/// restored; repositories only record them. Their attributes are applied
+/// by the caller once everything inside them has been written.
+fn backup_dir(
+    path: &Path,
+    relative_path: &Path,
+    destination: &Destination,
+    job: &BackupJob,
+) -> (FileReport, Option<ManifestEntry>) {
+    let mut file = FileReport::pending(relative_path);
+
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            file.error = Some(format!("Error reading directory metadata: {}", e));
+            return (file, None);
+        }
+    };
+    let xattrs = match read_xattrs(path, job.preserve) {
+        Ok(xattrs) => xattrs,
+        Err(e) => {
+            file.error = Some(format!("Error reading extended attributes: {}", e));
+            return (file, None);
+        }
+    };
+    let stored_path = match stored_path(relative_path, destination) {
+        Ok(path) => path,
+        Err(e) => {
+            file.error = Some(format!("Error encrypting directory name: {}", e));
+            return (file, None);
+        }
+    };
+    let target_path = destination.dir.join(&stored_path);
+
+    let recorded_before = destination
+        .manifest
+        .as_ref()
+        .and_then(|manifest| manifest.get(relative_path))
+        .is_some_and(|entry| entry.is_dir());
+    let existed = recorded_before
+        || destination
+            .base
+            .as_ref()
+            .is_some_and(|base| base.join(&stored_path).is_dir());
+    file.action = if existed {
+        FileAction::Skipped
+    } else {
+        FileAction::New
+    };
+
+    if job.dry_run {
+        return (file, None);
+    }
+
+    if destination.repository.is_none() {
+        // A file or link left at this path by an earlier run is replaced
+        let result = match fs::symlink_metadata(&target_path) {
+            Ok(existing) if existing.is_dir() => Ok(()),
+            Ok(_) => fs::remove_file(&target_path).and_then(|_| fs::create_dir_all(&target_path)),
+            Err(_) => fs::create_dir_all(&target_path),
+        };
+        if let Err(e) = result {
+            file.error = Some(format!("Error creating directory: {}", e));
+            return (file, None);
+        }
+        // Its mode is set again with the other attributes at the end
+        if destination.preserve.permissions {
+            if let Err(e) = make_writable(&target_path) {
+                file.error = Some(format!("Error making directory writable: {}", e));
+                return (file, None);
+            }
+        }
+    }
+
+    let recorded = ManifestEntry {
+        xattrs,
+        ..ManifestEntry::directory(&metadata)
+    };
+    (file, Some(recorded))
+}
+
+/// Copies a single source file into the target if it is new or modified.
+///
+/// When the target has a manifest, the decision is made from the recorded
+/// entry without examining the target copy.
+fn backup_file(
+    path: &Path,
+    relative_path: &Path,
+    destination: &Destination,
+    job: &BackupJob,
+    mp: &MultiProgress,
+) -> (FileReport, Option<ManifestEntry>) {
+    let mut file = FileReport::pending(relative_path);
+
+    // Encrypted names are the same in every run, so the previous copy is
+    // found under the same stored path
+    let stored_path = match stored_path(relative_path, destination) {
+        Ok(path) => path,
+        Err(e) => {
+            file.error = Some(format!("Error encrypting file name: {}", e));
+            return (file, None);
+        }
+    };
+    let target_path = destination.dir.join(&stored_path);
+    let base_path = destination
+        .base
+        .as_ref()
+        .map(|base| base.join(&stored_path));
+    let previous = destination
+        .manifest
+        .as_ref()
+        .and_then(|manifest| manifest.get(relative_path));
+
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            file.error = Some(format!("Error reading file metadata: {}", e));
+            return (file, None);
+        }
+    };
+    let xattrs = match read_xattrs(path, job.preserve) {
+        Ok(xattrs) => xattrs,
+        Err(e) => {
+            file.error = Some(format!("Error reading extended attributes: {}", e));
+            return (file, None);
+        }
+    };
+
+    // Determine if the file should be copied; files missing from an
+    // existing manifest are new
+    let changed = match (previous, &base_path) {
+        // The path held a link or directory in the previous run, or shared
+        // the copy of another hard link
+        (Some(entry), _) if !entry.is_file() || entry.hard_link.is_some() => Ok(Some(true)),
+        // Attributes can change without touching the contents
+        (Some(entry), _) if entry.xattrs != xattrs => Ok(Some(true)),
+        (Some(entry), _) => needs_copy_from_manifest(path, &metadata, entry, job.compare).map(Some),
+        (None, Some(base_path)) if destination.manifest.is_none() && base_path.exists() => {
+            needs_copy(path, base_path, job.compare).map(Some)
+        }
+        _ => Ok(None),
+    };
+    file.action = match changed {
+        Ok(Some(true)) => FileAction::Modified,
+        Ok(Some(false)) => FileAction::Skipped,
+        Ok(None) => FileAction::New,
+        Err(e) => {
+            file.error = Some(format!("Error comparing with target: {}", e));
+            return (file, None);
+        }
+    };
+
+    if job.dry_run {
+        return (file, None);
+    }
+
+    if file.action == FileAction::Skipped {
+        // Unchanged files in a snapshot share the previous snapshot's copy
+        if let Some(base_path) = base_path.filter(|p| *p != target_path) {
+            let result = target_path
+                .parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .and_then(|_| fs::hard_link(&base_path, &target_path));
+            if let Err(e) = result {
+                file.error = Some(format!("Error linking to previous snapshot: {}", e));
+                return (file, None);
+            }
+        }
+
+        let mut recorded = match previous {
+            Some(entry) => entry.clone(),
+            None => match hash_file(path) {
+                Ok(hash) => ManifestEntry::new(&metadata, hash),
+                Err(e) => {
+                    file.error = Some(format!("Error hashing file for the manifest: {}", e));
+                    return (file, None);
+                }
+            },
+        };
+        recorded.xattrs = xattrs;
+        return (file, Some(recorded));
+    }
+
+    // Ensure the target directory exists
+    if let Some(parent) = target_path.parent() {
+        if let Err(e) = fs::create_dir_all(parent) {
+            file.error = Some(format!("Error creating directories: {}", e));
+            return (file, None);
+        }
+    }
+
+    // Remove read-only attribute on Windows
+    #[cfg(target_os = "windows")]
+    {
+        if target_path.exists() {
+            if let Err(e) = crate::copy::remove_readonly_attribute(&target_path) {
+                file.error = Some(format!("Error removing read-only attribute: {}", e));
+                return (file, None);
+            }
+        }
+    }
+
+    // Leave data that would not get smaller as it is
+    let compression = match job.compression {
+        Compression::None => Compression::None,
+        _ if is_incompressible(path).unwrap_or(false) => Compression::None,
+        compression => compression,
+    };
+    let level = job
+        .compress_level
+        .unwrap_or_else(|| compression.default_level());
+    let transform = match (compression, &destination.cipher) {
+        (Compression::None, None) => Transform::None,
+        (compression, cipher) => Transform::Encode(compression, level, cipher.as_ref()),
+    };
+
+    // Copy the file with progress
+    match copy_with_progress(
+        path,
+        &target_path,
+        relative_path,
+        transform,
+        destination.preserve,
+        None,
+        "Backing up",
+        mp,
+    ) {
+        Ok((bytes, hash)) => {
+            file.bytes = bytes;
+            let mut recorded = ManifestEntry::new(&metadata, hash);
+            recorded.size = bytes;
+            recorded.compression = compression;
+            recorded.xattrs = xattrs;
+            (file, Some(recorded))
+        }
+        Err(e) => {
+            file.error = Some(format!("Error copying file: {}", e));
+            (file, None)
+        }
+    }
+}
+
+/// Records a symbolic link from the source, and recreates it in the target
+/// if it is new or changed. `source_path` is its path inside the source
+/// directory, without the subtree it is backed up under.
+///
+/// Encrypted targets and repositories do not hold the link itself, which
+/// would reveal its target; the manifest entry is its only record.
+fn backup_symlink(
+    path: &Path,
+    relative_path: &Path,
+    source_path: &Path,
+    destination: &Destination,
+    job: &BackupJob,
+) -> (FileReport, Option<ManifestEntry>) {
+    let mut file = FileReport::pending(relative_path);
+
+    let (metadata, target) = match fs::symlink_metadata(path)
+        .and_then(|metadata| fs::read_link(path).map(|target| (metadata, target)))
+    {
+        Ok(link) => link,
+        Err(e) => {
+            file.error = Some(format!("Error reading link: {}", e));
+            return (file, None);
+        }
+    };
+    if job.safe_links && points_outside(source_path, &target) {
+        file.error = Some(format!(
+            "Refusing link to {:?}, which points outside the source",
+            target
+        ));
+        return (file, None);
+    }
+    let Some(text) = target.to_str() else {
+        file.error = Some(format!(
+            "Error recording link: the target {:?} is not valid UTF-8",
+            target
+        ));
+        return (file, None);
+    };
+
+    let stored = destination.repository.is_none() && destination.cipher.is_none();
+    let target_path = destination.dir.join(relative_path);
+    let base_path = destination
+        .base
+        .as_ref()
+        .map(|base| base.join(relative_path));
+    let previous = destination
+        .manifest
+        .as_ref()
+        .and_then(|manifest| manifest.get(relative_path));
+
+    file.action = match (previous, &base_path) {
+        (Some(entry), _) if entry.link.as_deref() == Some(text) => FileAction::Skipped,
+        (Some(_), _) => FileAction::Modified,
+        (None, Some(base_path)) if destination.manifest.is_none() && stored => {
+            match fs::read_link(base_path) {
+                Ok(previous) if previous == target => FileAction::Skipped,
+                Ok(_) => FileAction::Modified,
+                Err(_) if fs::symlink_metadata(base_path).is_ok() => FileAction::Modified,
+                Err(_) => FileAction::New,
+            }
+        }
+        _ => FileAction::New,
+    };
+
+    if job.dry_run {
+        return (file, None);
+    }
+
+    let recorded = ManifestEntry::symlink(&metadata, text.to_string());
+    let unchanged_in_place =
+        file.action == FileAction::Skipped && base_path.as_ref() == Some(&target_path);
+    if !stored || unchanged_in_place {
+        return (file, Some(recorded));
+    }
+
+    let result = target_path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| replace_with_symlink(&target, &target_path));
+    match result {
+        Ok(()) => (file, Some(recorded)),
+        Err(e) => {
+            file.error = Some(format!("Error creating link: {}", e));
+            (file, None)
+        }
+    }
+}
+
+/// Records a FIFO, device node or socket from the source, and recreates it
+/// in the target if it is new or changed.
+///
+/// Device nodes can only be created by root. Otherwise, and in encrypted
+/// targets and repositories, the manifest entry is the only record.
+fn backup_special(
+    path: &Path,
+    relative_path: &Path,
+    kind: EntryKind,
+    destination: &Destination,
+    job: &BackupJob,
+) -> (FileReport, Option<ManifestEntry>) {
+    let mut file = FileReport::pending(relative_path);
+
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            file.error = Some(format!("Error reading file metadata: {}", e));
+            return (file, None);
+        }
+    };
+    let xattrs = match read_xattrs(path, job.preserve) {
+        Ok(xattrs) => xattrs,
+        Err(e) => {
+            file.error = Some(format!("Error reading extended attributes: {}", e));
+            return (file, None);
+        }
+    };
+    let recorded = ManifestEntry {
+        xattrs,
+        ..ManifestEntry::special(&metadata, kind)
+    };
+
+    let stored = destination.repository.is_none() && destination.cipher.is_none();
+    let target_path = destination.dir.join(relative_path);
+    let base_path = destination
+        .base
+        .as_ref()
+        .map(|base| base.join(relative_path));
+    let previous = destination
+        .manifest
+        .as_ref()
+        .and_then(|manifest| manifest.get(relative_path));
+
+    let same_node = |kind_before: Option<EntryKind>, rdev_before| {
+        kind_before == Some(kind) && rdev_before == recorded.rdev
+    };
+    file.action = match (previous, &base_path) {
+        (Some(entry), _) if same_node(Some(entry.kind), entry.rdev) => FileAction::Skipped,
+        (Some(_), _) => FileAction::Modified,
+        (None, Some(base_path)) if destination.manifest.is_none() && stored => {
+            match fs::symlink_metadata(base_path) {
+                Ok(existing)
+                    if same_node(special_kind(existing.file_type()), device_number(&existing)) =>
+                {
+                    FileAction::Skipped
+                }
+                Ok(_) => FileAction::Modified,
+                Err(_) => FileAction::New,
+            }
+        }
+        _ => FileAction::New,
+    };
+
+    if job.dry_run {
+        return (file, None);
+    }
+
+    let unchanged_in_place =
+        file.action == FileAction::Skipped && base_path.as_ref() == Some(&target_path);
+    if !stored || !can_create(kind) || unchanged_in_place {
+        return (file, Some(recorded));
+    }
+
+    let result = target_path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| {
+            replace_with(&target_path, |tmp| {
+                make_node(tmp, kind, recorded.mode, recorded.rdev)?;
+                if !destination.preserve.is_empty() {
+                    apply_attributes(&metadata, &recorded.xattrs, tmp, destination.preserve)?;
+                }
+                Ok(())
+            })
+        });
+    match result {
+        Ok(()) => (file, Some(recorded)),
+        Err(e) => {
+            file.error = Some(format!("Error creating special file: {}", e));
+            (file, None)
+        }
+    }
+}
+
+/// Links a later name of a hard-linked source file to the copy made for
+/// its first name, `first`, unless the two are linked already.
+///
+/// Repositories only record the link; the entry shares the chunks of the
+/// first name.
+fn backup_hard_link(
+    relative_path: &Path,
+    first: &FileReport,
+    first_recorded: Option<&ManifestEntry>,
+    destination: &Destination,
+    job: &BackupJob,
+) -> (FileReport, Option<ManifestEntry>) {
+    let mut file = FileReport::pending(relative_path);
+
+    let (stored_path, first_stored_path) = match stored_path(relative_path, destination)
+        .and_then(|path| stored_path(&first.path, destination).map(|first| (path, first)))
+    {
+        Ok(paths) => paths,
+        Err(e) => {
+            file.error = Some(format!("Error encrypting file name: {}", e));
+            return (file, None);
+        }
+    };
+    let target_path = destination.dir.join(&stored_path);
+    let base_path = destination
+        .base
+        .as_ref()
+        .map(|base| base.join(&stored_path));
+    let previous = destination
+        .manifest
+        .as_ref()
+        .and_then(|manifest| manifest.get(relative_path));
+
+    // A new copy of the first name needs a new link
+    let first_key = key(&first.path);
+    let linked_before =
+        previous.is_some_and(|entry| entry.hard_link.as_deref() == Some(first_key.as_str()));
+    file.action = if linked_before && first.action == FileAction::Skipped {
+        FileAction::Skipped
+    } else if previous.is_some() || base_path.as_ref().is_some_and(|path| path.exists()) {
+        FileAction::Modified
+    } else {
+        FileAction::New
+    };
+
+    if job.dry_run {
+        return (file, None);
+    }
+
+    let Some(first_recorded) = first_recorded else {
+        file.error = Some(format!("No copy of {:?} to link to", first.path));
+        return (file, None);
+    };
+    let recorded = ManifestEntry {
+        hard_link: Some(first_key),
+        ..first_recorded.clone()
+    };
+
+    let unchanged_in_place =
+        file.action == FileAction::Skipped && base_path.as_ref() == Some(&target_path);
+    if destination.repository.is_some() || unchanged_in_place {
+        return (file, Some(recorded));
+    }
+
+    let first_target_path = destination.dir.join(first_stored_path);
+    let result = target_path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| replace_with(&target_path, |tmp| fs::hard_link(&first_target_path, tmp)));
+    match result {
+        Ok(()) => (file, Some(recorded)),
+        Err(e) => {
+            file.error = Some(format!("Error creating hard link: {}", e));
+            (file, None)
+        }
+    }
+}
+
+/// Stores a single source file in the repository if it is new or modified.
+fn store_file(
+    path: &Path,
+    relative_path: &Path,
+    destination: &Destination,
+    repository: &Repository,
+    job: &BackupJob,
+    mp: &MultiProgress,
+) -> (FileReport, Option<ManifestEntry>) {
+    let mut file = FileReport::pending(relative_path);
+
+    let previous = destination
+        .manifest
+        .as_ref()
+        .and_then(|manifest| manifest.get(relative_path));
+
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            file.error = Some(format!("Error reading file metadata: {}", e));
+            return (file, None);
+        }
+    };
+    let xattrs = match read_xattrs(path, job.preserve) {
+        Ok(xattrs) => xattrs,
+        Err(e) => {
+            file.error = Some(format!("Error reading extended attributes: {}", e));
+            return (file, None);
+        }
+    };
+
+    // Determine if the file should be stored again
+    file.action = match previous {
+        Some(entry) if !entry.is_file() || entry.hard_link.is_some() => FileAction::Modified,
+        Some(entry) if entry.xattrs != xattrs => FileAction::Modified,
+        Some(entry) => match needs_copy_from_manifest(path, &metadata, entry, job.compare) {
+            Ok(true) => FileAction::Modified,
+            Ok(false) => FileAction::Skipped,
+            Err(e) => {
+                file.error = Some(format!("Error comparing with previous snapshot: {}", e));
+                return (file, None);
+            }
+        },
+        None => FileAction::New,
+    };
+
+    if job.dry_run {
+        return (file, None);
+    }
+    if file.action == FileAction::Skipped {
+        return (file, previous.cloned());
+    }
+
+    match repository.store_file(path, relative_path, mp) {
+        Ok(stored) => {
+            // Only chunks the repository did not hold yet count as copied
+            file.bytes = stored.new_bytes;
+            let mut recorded = ManifestEntry::new(&metadata, stored.hash);
+            recorded.size = stored.size;
+            recorded.chunks = stored.chunks;
+            recorded.xattrs = xattrs;
+            (file, Some(recorded))
+        }
+        Err(e) => {
+            file.error = Some(format!("Error storing file: {}", e));
+            (file, None)
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::testing::scratch;
+
+    fn report_for<'a>(report: &'a BackupReport, path: &str) -> &'a FileReport {
+        report
+            .files
+            .iter()
+            .find(|file| file.path == Path::new(path))
+            .unwrap()
+    }
+
+    #[test]
+    fn safe_links_are_checked_inside_each_source() {
+        let dir = scratch("safe-links");
+        for source in ["etc", "srv"] {
+            fs::create_dir(dir.join(source)).unwrap();
+        }
+        std::os::unix::fs::symlink("../outside", dir.join("etc/escape")).unwrap();
+        std::os::unix::fs::symlink("inside", dir.join("etc/stays")).unwrap();
+
+        let report = BackupJob::new(dir.join("etc"), dir.join("out"))
+            .add_source(dir.join("srv"))
+            .safe_links(true)
+            .run()
+            .unwrap();
+        assert!(report_for(&report, "etc/escape").is_failed());
+        assert!(!report_for(&report, "etc/stays").is_failed());
+        assert!(fs::symlink_metadata(dir.join("out/etc/escape")).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn previewed_deletions_stay_in_the_manifest() {
+        let dir = scratch("delete-dry-run");
+        let (source, target) = (dir.join("src"), dir.join("out"));
+        fs::create_dir(&source).unwrap();
+        for name in ["a", "b"] {
+            fs::write(source.join(name), name).unwrap();
+        }
+        BackupJob::new(&source, &target).run().unwrap();
+
+        fs::remove_file(source.join("a")).unwrap();
+        let report = BackupJob::new(&source, &target)
+            .delete(true)
+            .delete_dry_run(true)
+            .run()
+            .unwrap();
+        assert_eq!(report.deleted(), 0);
+        assert_eq!(report.would_delete(), 1);
+        assert!(target.join("a").exists());
+        let manifest = Manifest::load(&target).unwrap().unwrap();
+        assert!(manifest.get(Path::new("a")).is_some());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn subtree_roots_are_reported_without_a_separator() {
+        let dir = scratch("subtree-roots");
+        for source in ["etc", "srv"] {
+            fs::create_dir(dir.join(source)).unwrap();
+        }
+
+        let report = BackupJob::new(dir.join("etc"), dir.join("out"))
+            .add_source(dir.join("srv"))
+            .run()
+            .unwrap();
+        assert_eq!(report.source, dir.join("etc"));
+        let paths: Vec<_> = report.files.iter().map(|file| &file.path).collect();
+        assert_eq!(paths, [Path::new("etc"), Path::new("srv")]);
+        assert!(report.to_json().contains(r#""path": "etc","#));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,57 @@
+//! Differential backup engine behind the `srb` command-line tool.
+//!
+//! A backup is described with a [`BackupJob`] and executed with
+//! [`BackupJob::run`], which returns a [`BackupReport`] describing what
+//! happened to every file.
+//!
+//! ```no_run
+//! use srb::BackupJob;
+//!
+//! let report = BackupJob::new("/home/user/documents", "/mnt/backup/documents")
+//!     .run()
+//!     .expect("backup failed");
+//! println!("{} files copied", report.copied());
+//! ```
+
+mod compare;
+mod compress;
+mod config;
+mod copy;
+mod crypto;
+mod error;
+mod filter;
+mod job;
+mod manifest;
+mod metadata;
+mod mirror;
+mod prune;
+mod report;
+mod repository;
+mod restore;
+mod snapshot;
+mod sparse;
+mod special;
+mod symlink;
+#[cfg(test)]
+mod testing;
+mod verify;
+mod xattrs;
+
+pub use compare::CompareMode;
+pub use compress::Compression;
+pub use config::{Config, Profile};
+pub use crypto::{is_encrypted, EncryptionKey, ENCRYPTION_FILE};
+pub use error::{Error, Result};
+pub use filter::IGNORE_FILE;
+pub use job::BackupJob;
+pub use manifest::{EntryKind, Manifest, ManifestEntry, MANIFEST_DIR, MANIFEST_FILE};
+pub use metadata::Preserve;
+pub use prune::{parse_duration, prune, PruneEntry, PruneReport, RetentionPolicy};
+pub use report::{BackupReport, FileAction, FileReport};
+pub use repository::REPOSITORY_FILE;
+pub use restore::RestoreJob;
+pub use snapshot::{
+    find_snapshot, latest_snapshot, list_snapshots, Snapshot, LATEST_LINK, SNAPSHOT_FORMAT,
+};
+pub use symlink::SymlinkMode;
+pub use verify::{VerifyEntry, VerifyJob, VerifyReport, VerifyStatus};
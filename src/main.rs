@@ -1,11 +1,34 @@
-use std::fs::{self, File};
-use std::io::{self, Read, Write};
-use std::path::Path;
-use walkdir::WalkDir;
-
-use clap::Parser;
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use chrono::TimeDelta;
+use clap::{ArgGroup, Args, Parser, Subcommand, ValueEnum};
+use indicatif::HumanBytes;
+use srb::{
+    BackupJob, BackupReport, CompareMode, Compression, Config, EncryptionKey, FileAction, Preserve,
+    Profile, PruneReport, RestoreJob, RetentionPolicy, SymlinkMode, VerifyJob, VerifyStatus,
+};
+
+/// Exit code when the command completed without errors.
+const EXIT_SUCCESS: u8 = 0;
+/// Exit code when the command ran but some entries failed.
+const EXIT_PARTIAL: u8 = 1;
+/// Exit code when the command could not start at all.
+const EXIT_FATAL: u8 = 2;
+
+/// Environment variable read for the passphrase of an encrypted backup
+/// before asking on the terminal.
+const PASSPHRASE_VAR: &str = "SRB_PASSPHRASE";
+
+/// Output format for the end-of-run report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ReportFormat {
+    /// Human-readable summary
+    Text,
+    /// JSON document with one entry per file
+    Json,
+}
 
 /// A simple Rust program for differential backup
 #[derive(Parser, Debug)]
@@ -13,199 +36,771 @@ use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 #[command(author = "Joshua Vaughn <https://github.com/010josh010>")]
 #[command(version = "0.1.0")]
 #[command(about = "Performs differential backups from a source directory to a target directory.", long_about = None)]
-struct Args {
-    /// Source directory to backup
-    #[arg(short = 's', long)]
-    source_dir: String,
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    backup: BackupArgs,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Remove old snapshots according to a retention policy
+    Prune(PruneArgs),
+    /// Copy files from a backup back into a directory
+    Restore(RestoreArgs),
+    /// Check that a backup still matches its source or manifest
+    Verify(VerifyArgs),
+    /// Run backup profiles from the config file
+    Run(RunArgs),
+}
+
+/// Options for a backup run, given without a subcommand
+#[derive(Args, Debug)]
+#[command(group(ArgGroup::new("encryption").args(["encrypt", "key_file"]).multiple(true)))]
+struct BackupArgs {
+    /// Source directory to backup; repeat to back up several directories,
+    /// each into a subtree of the target named after it
+    #[arg(short = 's', long, required = true)]
+    source_dir: Vec<String>,
 
     /// Target directory where backup will be stored
+    #[arg(short = 't', long, required = true)]
+    target_dir: Option<String>,
+
+    /// How to decide whether an existing target file is out of date
+    #[arg(long, value_enum, default_value_t = CompareMode::Mtime)]
+    compare: CompareMode,
+
+    /// Only back up files matching this glob (repeatable)
+    #[arg(long, value_name = "GLOB")]
+    include: Vec<String>,
+
+    /// Skip files and directories matching this glob (repeatable)
+    #[arg(long, value_name = "GLOB")]
+    exclude: Vec<String>,
+
+    /// What to do with symbolic links in the source
+    #[arg(long, value_enum, value_name = "MODE", default_value_t = SymlinkMode::Preserve)]
+    symlinks: SymlinkMode,
+
+    /// Refuse symbolic links that point outside the source directory
+    #[arg(long)]
+    safe_links: bool,
+
+    /// Preserve timestamps, permissions and ownership (same as --times --perms --owner)
+    #[arg(short = 'a', long)]
+    archive: bool,
+
+    /// Preserve access and modification times
+    #[arg(long)]
+    times: bool,
+
+    /// Preserve permission bits
+    #[arg(short = 'p', long)]
+    perms: bool,
+
+    /// Preserve owner and group (requires root)
+    #[arg(short = 'o', long)]
+    owner: bool,
+
+    /// Preserve extended attributes
+    #[arg(short = 'X', long)]
+    xattrs: bool,
+
+    /// Preserve POSIX ACLs
+    #[arg(short = 'A', long)]
+    acls: bool,
+
+    /// Number of files to copy in parallel
+    #[arg(short = 'j', long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+    jobs: u16,
+
+    /// Show what would be copied or deleted without touching the target
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Write each run into a new timestamped snapshot directory, hard-linking unchanged files
+    #[arg(long, conflicts_with = "delete")]
+    snapshot: bool,
+
+    /// Store the backup as deduplicated chunks in a repository; every run is a snapshot
+    #[arg(long, conflicts_with_all = ["delete", "snapshot"])]
+    repository: bool,
+
+    /// Compress copied files; already-compressed formats are stored as they are
+    #[arg(long, value_enum, value_name = "ALGORITHM", default_value_t = Compression::None, conflicts_with = "repository")]
+    compress: Compression,
+
+    /// Compression level (zstd 1-22, default 3; gzip 0-9, default 6)
+    #[arg(long, value_name = "LEVEL", allow_negative_numbers = true)]
+    compress_level: Option<i32>,
+
+    /// Encrypt the backup with a passphrase, taken from SRB_PASSPHRASE or asked for
+    #[arg(long, conflicts_with = "repository")]
+    encrypt: bool,
+
+    /// Encrypt the backup with the contents of this file instead of a passphrase
+    #[arg(long, value_name = "PATH", conflicts_with = "repository")]
+    key_file: Option<String>,
+
+    /// Also encrypt file and directory names
+    #[arg(long, requires = "encryption")]
+    encrypt_names: bool,
+
+    /// Remove files from the target that no longer exist in the source
+    #[arg(long, visible_alias = "mirror")]
+    delete: bool,
+
+    /// List what --delete would remove without removing anything
+    #[arg(long, requires = "delete")]
+    delete_dry_run: bool,
+
+    /// Format of the report printed at the end of the run
+    #[arg(long, value_enum, value_name = "FORMAT", default_value_t = ReportFormat::Text)]
+    report: ReportFormat,
+
+    /// Also write a JSON report to this file
+    #[arg(long, value_name = "PATH")]
+    report_file: Option<String>,
+
+    /// Largest percentage of the target's files --delete may remove in one run
+    #[arg(long, value_name = "PERCENT", default_value_t = 50, value_parser = clap::value_parser!(u8).range(0..=100))]
+    max_delete: u8,
+}
+
+#[derive(Args, Debug)]
+struct PruneArgs {
+    /// Target directory holding the snapshots
+    #[arg(short = 't', long)]
+    target_dir: String,
+
+    /// Keep the N most recent snapshots
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    keep_last: usize,
+
+    /// Keep the newest snapshot of each of the last N days
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    keep_daily: usize,
+
+    /// Keep the newest snapshot of each of the last N weeks
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    keep_weekly: usize,
+
+    /// Keep the newest snapshot of each of the last N months
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    keep_monthly: usize,
+
+    /// Keep every snapshot taken within this long of the newest one, e.g. 7d, 2w, 6m, 1y
+    #[arg(long, value_name = "DURATION", value_parser = srb::parse_duration)]
+    keep_within: Option<TimeDelta>,
+
+    /// Show which snapshots would be removed without removing them
+    #[arg(long)]
+    dry_run: bool,
+}
+
+#[derive(Args, Debug)]
+struct RestoreArgs {
+    /// Backup directory to restore from
+    #[arg(short = 't', long)]
+    target_dir: String,
+
+    /// Directory to restore the files into
+    #[arg(short = 'd', long)]
+    destination: String,
+
+    /// Snapshot to restore; defaults to the newest one
+    #[arg(long, value_name = "ID")]
+    snapshot: Option<String>,
+
+    /// Only restore files matching these globs
+    #[arg(value_name = "GLOB")]
+    paths: Vec<String>,
+
+    /// Skip files and directories matching this glob (repeatable)
+    #[arg(long, value_name = "GLOB")]
+    exclude: Vec<String>,
+
+    /// Overwrite local files that are newer than the backup copy
+    #[arg(short = 'f', long)]
+    force: bool,
+
+    /// Show what would be restored without writing anything
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Key file of an encrypted backup; without it the passphrase is asked for
+    #[arg(long, value_name = "PATH")]
+    key_file: Option<String>,
+}
+
+#[derive(Args, Debug)]
+struct VerifyArgs {
+    /// Source directory the backup was made from; repeat for a backup of
+    /// several directories, or omit to check against the manifest
+    #[arg(short = 's', long)]
+    source_dir: Vec<String>,
+
+    /// Backup directory to check
     #[arg(short = 't', long)]
     target_dir: String,
+
+    /// Snapshot to check; defaults to the newest one
+    #[arg(long, value_name = "ID")]
+    snapshot: Option<String>,
+
+    /// Only check files matching this glob (repeatable)
+    #[arg(long, value_name = "GLOB")]
+    include: Vec<String>,
+
+    /// How the backup treated symbolic links in the source
+    #[arg(long, value_enum, value_name = "MODE", default_value_t = SymlinkMode::Preserve)]
+    symlinks: SymlinkMode,
+
+    /// Skip files and directories matching this glob (repeatable)
+    #[arg(long, value_name = "GLOB")]
+    exclude: Vec<String>,
+
+    /// Key file of an encrypted backup; without it the passphrase is asked for
+    #[arg(long, value_name = "PATH")]
+    key_file: Option<String>,
 }
 
-fn main() {
+#[derive(Args, Debug)]
+struct RunArgs {
+    /// Profile to run
+    #[arg(required_unless_present = "all", conflicts_with = "all")]
+    profile: Option<String>,
+
+    /// Run every profile in the config file, in name order
+    #[arg(long)]
+    all: bool,
+
+    /// Config file to read instead of ~/.config/srb/config.toml
+    #[arg(long, value_name = "PATH")]
+    config: Option<PathBuf>,
+
+    /// Source directory to use instead of the profile's (repeatable)
+    #[arg(short = 's', long, conflicts_with = "all")]
+    source_dir: Vec<String>,
+
+    /// Target directory to use instead of the profile's
+    #[arg(short = 't', long, conflicts_with = "all")]
+    target_dir: Option<String>,
+
+    /// Compare mode to use instead of the profile's
+    #[arg(long, value_enum)]
+    compare: Option<CompareMode>,
+
+    /// Only back up files matching this glob, on top of the profile's (repeatable)
+    #[arg(long, value_name = "GLOB")]
+    include: Vec<String>,
+
+    /// Skip files and directories matching this glob, on top of the profile's (repeatable)
+    #[arg(long, value_name = "GLOB")]
+    exclude: Vec<String>,
+
+    /// Number of files to copy in parallel
+    #[arg(short = 'j', long, value_name = "N", value_parser = clap::value_parser!(u16).range(1..))]
+    jobs: Option<u16>,
+
+    /// Format of the report printed at the end of each run
+    #[arg(long, value_enum, value_name = "FORMAT", default_value_t = ReportFormat::Text)]
+    report: ReportFormat,
+
+    /// Also write a JSON report to this file instead of the profile's
+    #[arg(long, value_name = "PATH", conflicts_with = "all")]
+    report_file: Option<String>,
+
+    /// Largest percentage of the target's files --delete may remove, instead of the profile's
+    #[arg(long, value_name = "PERCENT", value_parser = clap::value_parser!(u8).range(0..=100))]
+    max_delete: Option<u8>,
+
+    /// Show what would be copied, deleted or pruned without changing anything
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Keep the N most recent snapshots when pruning
+    #[arg(long, value_name = "N")]
+    keep_last: Option<usize>,
+
+    /// Keep the newest snapshot of each of the last N days when pruning
+    #[arg(long, value_name = "N")]
+    keep_daily: Option<usize>,
+
+    /// Keep the newest snapshot of each of the last N weeks when pruning
+    #[arg(long, value_name = "N")]
+    keep_weekly: Option<usize>,
+
+    /// Keep the newest snapshot of each of the last N months when pruning
+    #[arg(long, value_name = "N")]
+    keep_monthly: Option<usize>,
+
+    /// Keep every snapshot taken within this long of the newest one when pruning
+    #[arg(long, value_name = "DURATION", value_parser = srb::parse_duration)]
+    keep_within: Option<TimeDelta>,
+}
+
+fn main() -> ExitCode {
     // Parse command-line arguments using clap
-    let args = Args::parse();
+    let cli = Cli::parse();
+
+    let code = match &cli.command {
+        Some(Command::Prune(args)) => run_prune(args),
+        Some(Command::Restore(args)) => run_restore(args),
+        Some(Command::Verify(args)) => run_verify(args),
+        Some(Command::Run(args)) => run_profiles(args),
+        None => run_backup(&cli.backup),
+    };
+    ExitCode::from(code)
+}
 
-    let source_dir = Path::new(&args.source_dir);
-    let target_dir = Path::new(&args.target_dir);
+fn run_backup(args: &BackupArgs) -> u8 {
+    let (Some((source_dir, more_sources)), Some(target_dir)) =
+        (args.source_dir.split_first(), &args.target_dir)
+    else {
+        unreachable!("clap requires the source and target directories");
+    };
+
+    let preserve = Preserve {
+        times: args.archive || args.times,
+        permissions: args.archive || args.perms,
+        ownership: args.archive || args.owner,
+        xattrs: args.xattrs,
+        acls: args.acls,
+    };
+
+    let mut job = BackupJob::new(source_dir, target_dir)
+        .progress(!args.dry_run)
+        .dry_run(args.dry_run)
+        .compare(args.compare)
+        .preserve(preserve)
+        .delete(args.delete)
+        .delete_dry_run(args.delete_dry_run)
+        .max_delete_percent(args.max_delete)
+        .jobs(args.jobs as usize)
+        .snapshot(args.snapshot)
+        .repository(args.repository)
+        .compress(args.compress)
+        .symlinks(args.symlinks)
+        .safe_links(args.safe_links);
+    for source_dir in more_sources {
+        job = job.add_source(source_dir);
+    }
+    if let Some(level) = args.compress_level {
+        job = job.compress_level(level);
+    }
+    if args.encrypt || args.key_file.is_some() {
+        // A new passphrase is asked for twice to catch typing mistakes
+        let confirm = !srb::is_encrypted(Path::new(target_dir));
+        match read_key(args.key_file.as_deref(), confirm) {
+            Ok(key) => job = job.encrypt(key).encrypt_names(args.encrypt_names),
+            Err(e) => {
+                eprintln!("{}", e);
+                return EXIT_FATAL;
+            }
+        }
+    }
+    for pattern in &args.include {
+        job = job.include(pattern);
+    }
+    for pattern in &args.exclude {
+        job = job.exclude(pattern);
+    }
 
-    // Validate source directory
-    if !source_dir.is_dir() {
-        eprintln!("Source directory does not exist or is not a directory.");
-        return;
+    let report = match job.run() {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("{}", e);
+            return EXIT_FATAL;
+        }
+    };
+
+    match args.report {
+        ReportFormat::Json => println!("{}", report.to_json()),
+        ReportFormat::Text => print_summary(&report),
     }
 
-    // Validate and prepare the target directory
-    if target_dir.exists() {
-        if !target_dir.is_dir() {
-            eprintln!("Target path exists but is not a directory.");
-            return;
+    print_errors(&report);
+
+    if let Some(path) = &args.report_file {
+        if let Err(e) = fs::write(path, report.to_json()) {
+            eprintln!("Failed to write report to {}: {}", path, e);
+            return EXIT_PARTIAL;
         }
+    }
+
+    if report.is_success() {
+        EXIT_SUCCESS
     } else {
-        // Attempt to create the target directory
-        if let Err(e) = fs::create_dir_all(target_dir) {
-            eprintln!("Failed to create target directory: {}", e);
-            return;
+        EXIT_PARTIAL
+    }
+}
+
+/// Runs the profiles selected on the command line one after another,
+/// pruning each target afterwards when the profile has a retention policy.
+///
+/// Returns the worst exit code of all profiles.
+fn run_profiles(args: &RunArgs) -> u8 {
+    let Some(path) = args.config.clone().or_else(Config::default_path) else {
+        eprintln!("No config file found; give one with --config.");
+        return EXIT_FATAL;
+    };
+    let config = match Config::load(&path) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            return EXIT_FATAL;
         }
+    };
+
+    let names: Vec<&str> = match &args.profile {
+        Some(name) => vec![name],
+        None => config.profiles.keys().map(String::as_str).collect(),
+    };
+    if names.is_empty() {
+        eprintln!("No profiles defined in {}.", path.display());
+        return EXIT_FATAL;
     }
 
-    // Collect all files to process
-    let mut files_to_process = Vec::new();
-    for entry in WalkDir::new(source_dir) {
-        let entry = match entry {
-            Ok(e) => e,
+    let mut code = EXIT_SUCCESS;
+    for (index, name) in names.into_iter().enumerate() {
+        let profile = match config.profile(name) {
+            Ok(profile) => profile,
             Err(e) => {
-                eprintln!("Error reading entry: {}", e);
-                continue;
+                eprintln!("{}", e);
+                return EXIT_FATAL;
             }
         };
+        if args.all {
+            if index > 0 {
+                println!();
+            }
+            println!("== Profile {} ==", name);
+        }
 
-        let path = entry.path();
-        if path.is_file() {
-            files_to_process.push(entry);
+        let backup = profile_args(profile, args);
+        let mut result = run_backup(&backup);
+
+        let policy = RetentionPolicy {
+            keep_last: args.keep_last.unwrap_or(profile.retention.keep_last),
+            keep_daily: args.keep_daily.unwrap_or(profile.retention.keep_daily),
+            keep_weekly: args.keep_weekly.unwrap_or(profile.retention.keep_weekly),
+            keep_monthly: args.keep_monthly.unwrap_or(profile.retention.keep_monthly),
+            keep_within: args.keep_within.or(profile.retention.keep_within),
+        };
+        // A dry run may not have created the target yet
+        let target_dir = backup.target_dir.as_deref().unwrap_or_default();
+        // Pruning a plain target would remove backed-up data
+        let plain = !backup.snapshot && !backup.repository;
+        if !policy.is_empty() && plain {
+            eprintln!(
+                "Profile {} writes neither snapshots nor a repository; not pruning.",
+                name
+            );
+        } else if result != EXIT_FATAL && !policy.is_empty() && Path::new(target_dir).is_dir() {
+            result = result.max(prune_target(target_dir, &policy, args.dry_run));
         }
+        code = code.max(result);
     }
+    code
+}
 
-    // Create a MultiProgress to manage multiple progress bars
-    let mp = MultiProgress::new();
+/// Builds the backup options for `profile`, with the values given on the
+/// command line taking precedence.
+fn profile_args(profile: &Profile, args: &RunArgs) -> BackupArgs {
+    let path = |path: &Path| path.to_string_lossy().into_owned();
+    BackupArgs {
+        source_dir: if args.source_dir.is_empty() {
+            profile.source.iter().map(|source| path(source)).collect()
+        } else {
+            args.source_dir.clone()
+        },
+        target_dir: args
+            .target_dir
+            .clone()
+            .or_else(|| Some(path(&profile.target))),
+        compare: args.compare.unwrap_or(profile.compare),
+        include: [profile.include.as_slice(), &args.include].concat(),
+        exclude: [profile.exclude.as_slice(), &args.exclude].concat(),
+        symlinks: profile.symlinks,
+        safe_links: profile.safe_links,
+        archive: profile.archive,
+        times: false,
+        perms: false,
+        owner: false,
+        xattrs: profile.xattrs,
+        acls: profile.acls,
+        jobs: args.jobs.unwrap_or(profile.jobs).max(1),
+        dry_run: args.dry_run,
+        snapshot: profile.snapshot,
+        repository: profile.repository,
+        compress: profile.compress,
+        compress_level: profile.compress_level,
+        encrypt: profile.encrypt,
+        key_file: profile.key_file.as_deref().map(path),
+        encrypt_names: profile.encrypt_names,
+        delete: profile.delete,
+        delete_dry_run: false,
+        report: args.report,
+        report_file: args
+            .report_file
+            .clone()
+            .or_else(|| profile.report_file.as_deref().map(path)),
+        max_delete: args.max_delete.unwrap_or(profile.max_delete),
+    }
+}
 
-    // Create the overall progress bar
-    let pb = mp.add(ProgressBar::new(files_to_process.len() as u64));
-    pb.set_style(
-        ProgressStyle::default_bar()
-            .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} {msg}")
-            .expect("Failed to set progress bar template")
-            .progress_chars("#>-"),
-    );
+fn run_prune(args: &PruneArgs) -> u8 {
+    let policy = RetentionPolicy {
+        keep_last: args.keep_last,
+        keep_daily: args.keep_daily,
+        keep_weekly: args.keep_weekly,
+        keep_monthly: args.keep_monthly,
+        keep_within: args.keep_within,
+    };
+    prune_target(&args.target_dir, &policy, args.dry_run)
+}
 
-    // Process files
-    for entry in files_to_process {
-        let path = entry.path();
+fn prune_target(target_dir: &str, policy: &RetentionPolicy, dry_run: bool) -> u8 {
+    let report = match srb::prune(target_dir.as_ref(), policy, dry_run) {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("{}", e);
+            return EXIT_FATAL;
+        }
+    };
 
-        // Compute the relative path from the source directory
-        let relative_path = match path.strip_prefix(source_dir) {
-            Ok(p) => p,
-            Err(e) => {
-                eprintln!("Error computing relative path: {}", e);
-                pb.inc(1);
-                continue;
-            }
-        };
+    print_prune_summary(&report);
 
-        let target_path = target_dir.join(relative_path);
-
-        // Determine if the file should be copied
-        let should_copy = if target_path.exists() {
-            // Compare modification times
-            let source_modified = match fs::metadata(path).and_then(|m| m.modified()) {
-                Ok(time) => time,
-                Err(e) => {
-                    eprintln!("Error reading source modification time: {}", e);
-                    pb.inc(1);
-                    continue;
-                }
-            };
-            let target_modified = match fs::metadata(&target_path).and_then(|m| m.modified()) {
-                Ok(time) => time,
-                Err(e) => {
-                    eprintln!("Error reading target modification time: {}", e);
-                    pb.inc(1);
-                    continue;
-                }
-            };
-
-            source_modified > target_modified
-        } else {
-            true
-        };
+    if report.is_success() {
+        EXIT_SUCCESS
+    } else {
+        EXIT_PARTIAL
+    }
+}
 
-        if should_copy {
-            // Ensure the target directory exists
-            if let Some(parent) = target_path.parent() {
-                if let Err(e) = fs::create_dir_all(parent) {
-                    eprintln!("Error creating directories: {}", e);
-                    pb.inc(1);
-                    continue;
-                }
+fn run_restore(args: &RestoreArgs) -> u8 {
+    let mut job = RestoreJob::new(&args.target_dir, &args.destination)
+        .progress(!args.dry_run)
+        .force(args.force)
+        .dry_run(args.dry_run);
+    if let Some(name) = &args.snapshot {
+        job = job.snapshot(name);
+    }
+    for pattern in &args.paths {
+        job = job.include(pattern);
+    }
+    if args.key_file.is_some() || srb::is_encrypted(Path::new(&args.target_dir)) {
+        match read_key(args.key_file.as_deref(), false) {
+            Ok(key) => job = job.key(key),
+            Err(e) => {
+                eprintln!("{}", e);
+                return EXIT_FATAL;
             }
+        }
+    }
+    for pattern in &args.exclude {
+        job = job.exclude(pattern);
+    }
 
-            // Remove read-only attribute on Windows
-            #[cfg(target_os = "windows")]
-            {
-                if target_path.exists() {
-                    if let Err(e) = remove_readonly_attribute(&target_path) {
-                        eprintln!("Error removing read-only attribute: {}", e);
-                        pb.inc(1);
-                        continue;
-                    }
-                }
-            }
+    let report = match job.run() {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("{}", e);
+            return EXIT_FATAL;
+        }
+    };
 
-            // Copy the file with progress
-            if let Err(e) = copy_with_progress(path, &target_path, relative_path, &mp) {
-                eprintln!("Error copying file: {}", e);
-                pb.inc(1);
-                continue;
-            }
-        
-        } 
+    print_summary(&report);
+    print_errors(&report);
 
-        pb.inc(1);
+    if report.is_success() {
+        EXIT_SUCCESS
+    } else {
+        EXIT_PARTIAL
     }
-
-    pb.finish_with_message("Backup completed.");
 }
 
-#[cfg(target_os = "windows")]
-fn remove_readonly_attribute(target_path: &Path) -> std::io::Result<()> {
-    let metadata = fs::metadata(target_path)?;
-    let mut permissions = metadata.permissions();
+fn run_verify(args: &VerifyArgs) -> u8 {
+    let mut job = match args.source_dir.split_first() {
+        Some((source_dir, _)) => VerifyJob::new(source_dir, &args.target_dir),
+        None => VerifyJob::with_manifest(&args.target_dir),
+    }
+    .progress(true)
+    .symlinks(args.symlinks);
+    for source_dir in args.source_dir.iter().skip(1) {
+        job = job.add_source(source_dir);
+    }
+    if let Some(name) = &args.snapshot {
+        job = job.snapshot(name);
+    }
+    if args.key_file.is_some() || srb::is_encrypted(Path::new(&args.target_dir)) {
+        match read_key(args.key_file.as_deref(), false) {
+            Ok(key) => job = job.key(key),
+            Err(e) => {
+                eprintln!("{}", e);
+                return EXIT_FATAL;
+            }
+        }
+    }
+    for pattern in &args.include {
+        job = job.include(pattern);
+    }
+    for pattern in &args.exclude {
+        job = job.exclude(pattern);
+    }
 
-    // Check if the file is read-only
-    if permissions.readonly() {
-        permissions.set_readonly(false);
-        fs::set_permissions(target_path, permissions)?;
+    let report = match job.run() {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("{}", e);
+            return EXIT_FATAL;
+        }
+    };
+
+    for entry in report.problems() {
+        match &entry.detail {
+            Some(detail) => println!(
+                "{:<8}  {}  ({})",
+                entry.status,
+                entry.path.display(),
+                detail
+            ),
+            None => println!("{:<8}  {}", entry.status, entry.path.display()),
+        }
+    }
+    if let Some(name) = &report.snapshot {
+        println!("Snapshot {}", name);
+    }
+    println!(
+        "{} ok, {} missing, {} extra, {} mismatched, {} unreadable.",
+        report.count(VerifyStatus::Ok),
+        report.count(VerifyStatus::Missing),
+        report.count(VerifyStatus::Extra),
+        report.count(VerifyStatus::Mismatched),
+        report.errors.len()
+    );
+
+    if !report.errors.is_empty() {
+        eprintln!("\nErrors:");
+        for error in &report.errors {
+            eprintln!("  {}", error);
+        }
     }
 
-    Ok(())
+    if report.is_success() {
+        EXIT_SUCCESS
+    } else {
+        EXIT_PARTIAL
+    }
 }
 
-fn copy_with_progress(
-    src: &Path,
-    dst: &Path,
-    relative_path: &Path,
-    mp: &MultiProgress,
-) -> io::Result<()> {
-    let metadata = fs::metadata(src)?;
-    let total_size = metadata.len();
+/// Lists every snapshot with the decision taken for it.
+fn print_prune_summary(report: &PruneReport) {
+    for entry in &report.entries {
+        if entry.is_kept() {
+            println!(
+                "keep    {}  ({})",
+                entry.snapshot.name,
+                entry.reasons.join(", ")
+            );
+        } else if let Some(error) = &entry.error {
+            eprintln!("failed  {}  {}", entry.snapshot.name, error);
+        } else {
+            println!("remove  {}", entry.snapshot.name);
+        }
+    }
+    for error in &report.errors {
+        eprintln!("{}", error);
+    }
 
-    let mut src_file = File::open(src)?;
-    let mut dst_file = File::create(dst)?;
+    let removed = report.removed().filter(|e| e.error.is_none()).count();
+    if report.dry_run {
+        println!(
+            "Dry run: {} snapshots to remove, {} to free.",
+            removed,
+            HumanBytes(report.freed_bytes)
+        );
+    } else {
+        println!(
+            "{} snapshots removed, {} freed.",
+            removed,
+            HumanBytes(report.freed_bytes)
+        );
+    }
+}
 
-    // Create the per-file progress bar using MultiProgress
-    let pb = mp.add(ProgressBar::new(total_size));
-    pb.set_style(
-        ProgressStyle::default_bar()
-            .template("{spinner:.green} Backing up {msg}\n  {bar:40.cyan/blue} {bytes}/{total_bytes} ({bytes_per_sec}, ETA: {eta})")
-            .expect("Failed to set per-file progress bar template")
-            .progress_chars("#>-"),
-    );
+/// Prints the planned actions of a dry run, or the totals of a real run.
+fn print_summary(report: &BackupReport) {
+    if report.dry_run {
+        for file in report.files.iter().filter(|f| !f.is_failed()) {
+            println!("{:<8}  {}", file.action, file.path.display());
+        }
+        println!(
+            "Dry run: {} to copy, {} up to date, {} to delete.",
+            report.copied(),
+            report.skipped(),
+            report.would_delete()
+        );
+    } else {
+        for file in report
+            .files
+            .iter()
+            .filter(|f| f.action == FileAction::WouldDelete)
+        {
+            println!("Would delete {}", file.path.display());
+        }
+        if let Some(name) = &report.snapshot {
+            println!("Snapshot {}", name);
+        }
+        println!(
+            "{} copied, {} up to date, {} deleted, {} failed.",
+            report.copied(),
+            report.skipped(),
+            report.deleted(),
+            report.failed() + report.errors.len()
+        );
+    }
+}
 
-    // Set the message to the filename
-    pb.set_message(format!("{:?}", relative_path));
+/// Prints every error collected during the run to stderr.
+fn print_errors(report: &BackupReport) {
+    if report.is_success() {
+        return;
+    }
 
-    let mut buffer = [0u8; 8192];
-    loop {
-        let bytes_read = src_file.read(&mut buffer)?;
-        if bytes_read == 0 {
-            break;
+    eprintln!("\nErrors:");
+    for error in &report.errors {
+        eprintln!("  {}", error);
+    }
+    for file in report.failures() {
+        if let Some(error) = &file.error {
+            eprintln!("  {}: {}", file.path.display(), error);
         }
-        dst_file.write_all(&buffer[..bytes_read])?;
-        pb.inc(bytes_read as u64);
     }
+}
 
-    pb.finish_and_clear(); // Clear the per-file progress bar and message when done
+/// Returns the key of an encrypted backup: the key file when one is given,
+/// else the passphrase from SRB_PASSPHRASE or the terminal. With `confirm`
+/// a passphrase typed on the terminal is asked for twice.
+fn read_key(key_file: Option<&str>, confirm: bool) -> Result<EncryptionKey, String> {
+    if let Some(path) = key_file {
+        return Ok(EncryptionKey::File(path.into()));
+    }
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_VAR) {
+        return Ok(EncryptionKey::Passphrase(passphrase));
+    }
 
-    Ok(())
-}
\ No newline at end of file
+    let prompt = |text| {
+        rpassword::prompt_password(text).map_err(|e| format!("Failed to read passphrase: {}", e))
+    };
+    let passphrase = prompt("Passphrase: ")?;
+    if confirm && prompt("Repeat passphrase: ")? != passphrase {
+        return Err("Passphrases do not match.".to_string());
+    }
+    Ok(EncryptionKey::Passphrase(passphrase))
+}
@@ -0,0 +1,363 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File, Metadata};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use filetime::FileTime;
+use serde::{Deserialize, Serialize};
+
+use crate::compress::Compression;
+use crate::copy::temp_path;
+use crate::crypto::{decrypter, Cipher, Encrypter};
+use crate::special::device_number;
+
+/// Directory inside a backup holding srb's own bookkeeping files.
+pub const MANIFEST_DIR: &str = ".srb";
+
+/// Name of the manifest file inside [`MANIFEST_DIR`].
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+const MANIFEST_VERSION: u32 = 1;
+
+/// The kind of source entry a [`ManifestEntry`] describes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EntryKind {
+    #[default]
+    File,
+    Dir,
+    Symlink,
+    Fifo,
+    CharDevice,
+    BlockDevice,
+    Socket,
+}
+
+impl EntryKind {
+    fn is_file(&self) -> bool {
+        *self == EntryKind::File
+    }
+
+    /// Returns true for FIFOs, device nodes and sockets.
+    pub fn is_special(&self) -> bool {
+        matches!(
+            self,
+            EntryKind::Fifo | EntryKind::CharDevice | EntryKind::BlockDevice | EntryKind::Socket
+        )
+    }
+}
+
+/// What srb recorded about one backed-up file, directory, symbolic link or
+/// special file.
+///
+/// Size, modification time and mode are those of the source entry when it
+/// was backed up; the hash is the BLAKE3 hash of the data that was written.
+/// Only regular files have contents; the size of anything else is 0.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    #[serde(default, skip_serializing_if = "EntryKind::is_file")]
+    pub kind: EntryKind,
+    pub size: u64,
+    /// Modification time, in whole seconds since the Unix epoch.
+    pub mtime: i64,
+    /// Sub-second part of the modification time.
+    pub mtime_nsec: u32,
+    /// Unix permission bits; 0o444 or 0o644 on Windows.
+    pub mode: u32,
+    /// Hex-encoded BLAKE3 hash of the contents.
+    pub hash: String,
+    /// How the backup copy is compressed; size and hash describe the
+    /// uncompressed contents.
+    #[serde(default, skip_serializing_if = "Compression::is_none")]
+    pub compression: Compression,
+    /// Hashes of the chunks holding the contents, in order. Only used in
+    /// repository snapshot indexes.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub chunks: Vec<String>,
+    /// Target of a symbolic link, recorded instead of contents.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link: Option<String>,
+    /// Device number of a device node.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub rdev: u64,
+    /// Manifest key of the file this one is a hard link of, in the source
+    /// and in the backup. Size and hash are those of that file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hard_link: Option<String>,
+    /// Extended attributes and ACLs, by name, when the backup preserved
+    /// them. Stored in base64.
+    #[serde(
+        default,
+        skip_serializing_if = "BTreeMap::is_empty",
+        with = "base64_values"
+    )]
+    pub xattrs: BTreeMap<String, Vec<u8>>,
+}
+
+fn is_zero(value: &u64) -> bool {
+    *value == 0
+}
+
+/// Writes extended attribute values as base64 strings.
+mod base64_values {
+    use std::collections::BTreeMap;
+
+    use data_encoding::BASE64;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        values: &BTreeMap<String, Vec<u8>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(
+            values
+                .iter()
+                .map(|(name, value)| (name, BASE64.encode(value))),
+        )
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<BTreeMap<String, Vec<u8>>, D::Error> {
+        BTreeMap::<String, String>::deserialize(deserializer)?
+            .into_iter()
+            .map(|(name, value)| {
+                let value = BASE64.decode(value.as_bytes()).map_err(D::Error::custom)?;
+                Ok((name, value))
+            })
+            .collect()
+    }
+}
+
+impl ManifestEntry {
+    /// Describes a source file with the given metadata and content hash.
+    pub(crate) fn new(metadata: &Metadata, hash: blake3::Hash) -> Self {
+        let mtime = FileTime::from_last_modification_time(metadata);
+        ManifestEntry {
+            kind: EntryKind::File,
+            size: metadata.len(),
+            mtime: mtime.unix_seconds(),
+            mtime_nsec: mtime.nanoseconds(),
+            mode: mode(metadata),
+            hash: hash.to_hex().to_string(),
+            compression: Compression::None,
+            chunks: Vec::new(),
+            link: None,
+            rdev: 0,
+            hard_link: None,
+            xattrs: BTreeMap::new(),
+        }
+    }
+
+    /// Describes a symbolic link with the given metadata, as returned by
+    /// [`fs::symlink_metadata`], pointing at `target`.
+    pub(crate) fn symlink(metadata: &Metadata, target: String) -> Self {
+        ManifestEntry {
+            kind: EntryKind::Symlink,
+            size: 0,
+            link: Some(target),
+            ..ManifestEntry::new(metadata, blake3::hash(&[]))
+        }
+    }
+
+    /// Describes a directory with the given metadata.
+    pub(crate) fn directory(metadata: &Metadata) -> Self {
+        ManifestEntry {
+            kind: EntryKind::Dir,
+            size: 0,
+            ..ManifestEntry::new(metadata, blake3::hash(&[]))
+        }
+    }
+
+    /// Describes a FIFO, device node or socket of the given kind.
+    pub(crate) fn special(metadata: &Metadata, kind: EntryKind) -> Self {
+        ManifestEntry {
+            kind,
+            size: 0,
+            rdev: device_number(metadata),
+            ..ManifestEntry::new(metadata, blake3::hash(&[]))
+        }
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.kind == EntryKind::File
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.kind == EntryKind::Dir
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.kind == EntryKind::Symlink
+    }
+
+    pub fn modified(&self) -> SystemTime {
+        let nanos = Duration::from_nanos(u64::from(self.mtime_nsec));
+        if self.mtime >= 0 {
+            UNIX_EPOCH + Duration::from_secs(self.mtime as u64) + nanos
+        } else {
+            UNIX_EPOCH - Duration::from_secs(self.mtime.unsigned_abs()) + nanos
+        }
+    }
+
+    pub(crate) fn file_time(&self) -> FileTime {
+        FileTime::from_unix_time(self.mtime, self.mtime_nsec)
+    }
+}
+
+/// Index of every file in a backup directory, stored as
+/// `.srb/manifest.json` next to the files it describes. Repositories use
+/// the same format for their snapshot indexes.
+///
+/// Keys are relative paths with `/` separators.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    version: u32,
+    pub files: BTreeMap<String, ManifestEntry>,
+}
+
+impl Manifest {
+    pub fn new() -> Self {
+        Manifest {
+            version: MANIFEST_VERSION,
+            files: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the manifest path for the backup directory `dir`.
+    pub fn path(dir: &Path) -> PathBuf {
+        dir.join(MANIFEST_DIR).join(MANIFEST_FILE)
+    }
+
+    /// Reads the manifest of the backup directory `dir`, returning `None`
+    /// when it has none.
+    pub fn load(dir: &Path) -> io::Result<Option<Manifest>> {
+        Self::load_with(dir, None)
+    }
+
+    /// Like [`load`](Self::load), decrypting the manifest of an encrypted
+    /// backup with `cipher`.
+    pub(crate) fn load_with(dir: &Path, cipher: Option<&Cipher>) -> io::Result<Option<Manifest>> {
+        match Self::read(&Self::path(dir), cipher) {
+            Ok(manifest) => Ok(Some(manifest)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Writes the manifest into the backup directory `dir`, replacing any
+    /// previous one atomically.
+    pub fn save(&self, dir: &Path) -> io::Result<()> {
+        self.save_with(dir, None)
+    }
+
+    /// Like [`save`](Self::save), encrypting the manifest with `cipher`.
+    pub(crate) fn save_with(&self, dir: &Path, cipher: Option<&Cipher>) -> io::Result<()> {
+        self.write(&Self::path(dir), cipher)
+    }
+
+    /// Reads a manifest, or a repository snapshot index, from `path`.
+    pub(crate) fn read(path: &Path, cipher: Option<&Cipher>) -> io::Result<Manifest> {
+        let file = BufReader::new(File::open(path)?);
+        let manifest: Manifest = serde_json::from_reader(decrypter(file, cipher)?)?;
+        if manifest.version != MANIFEST_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported manifest version {}", manifest.version),
+            ));
+        }
+        Ok(manifest)
+    }
+
+    /// Writes the manifest to `path` through a temporary file, creating the
+    /// parent directory if needed.
+    pub(crate) fn write(&self, path: &Path, cipher: Option<&Cipher>) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let tmp = temp_path(path);
+
+        let result = File::create(&tmp).and_then(|file| {
+            let mut writer = Encrypter::new(BufWriter::new(file), cipher)?;
+            serde_json::to_writer(&mut writer, self)?;
+            let mut writer = writer.finish()?;
+            writer.flush()?;
+            writer.into_inner().map_err(|e| e.into_error())?.sync_all()
+        });
+        let result = result.and_then(|_| fs::rename(&tmp, path));
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp);
+        }
+        result
+    }
+
+    pub fn get(&self, relative_path: &Path) -> Option<&ManifestEntry> {
+        self.files.get(&key(relative_path))
+    }
+
+    pub fn insert(&mut self, relative_path: &Path, entry: ManifestEntry) {
+        self.files.insert(key(relative_path), entry);
+    }
+
+    pub fn remove(&mut self, relative_path: &Path) -> Option<ManifestEntry> {
+        self.files.remove(&key(relative_path))
+    }
+}
+
+impl Default for Manifest {
+    fn default() -> Self {
+        Manifest::new()
+    }
+}
+
+/// Returns the manifest key for a relative path.
+pub(crate) fn key(relative_path: &Path) -> String {
+    let mut key = String::new();
+    for component in relative_path.components() {
+        if let Component::Normal(name) = component {
+            if !key.is_empty() {
+                key.push('/');
+            }
+            key.push_str(&name.to_string_lossy());
+        }
+    }
+    key
+}
+
+/// Returns true for the bookkeeping directory at the root of a backup,
+/// which is never treated as backed-up data.
+pub(crate) fn is_manifest_dir(relative_path: &Path) -> bool {
+    relative_path == Path::new(MANIFEST_DIR)
+}
+
+#[cfg(unix)]
+fn mode(metadata: &Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+fn mode(metadata: &Metadata) -> u32 {
+    if metadata.permissions().readonly() {
+        0o444
+    } else {
+        0o644
+    }
+}
+
+/// Sets the permission bits recorded in a manifest entry on `path`.
+pub(crate) fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))
+    }
+    #[cfg(not(unix))]
+    {
+        let mut permissions = fs::metadata(path)?.permissions();
+        permissions.set_readonly(mode & 0o222 == 0);
+        fs::set_permissions(path, permissions)
+    }
+}
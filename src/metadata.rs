@@ -0,0 +1,91 @@
+use std::fs::{self, Metadata};
+use std::io;
+use std::path::Path;
+
+use filetime::FileTime;
+
+/// Which source attributes are carried over to copied files.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Preserve {
+    /// Access and modification times.
+    pub times: bool,
+    /// Permission bits (the read-only flag on Windows).
+    pub permissions: bool,
+    /// Owning user and group. Only applied when running as root on Unix.
+    pub ownership: bool,
+    /// Extended attributes other than ACLs. Unix only.
+    pub xattrs: bool,
+    /// POSIX ACLs, stored as the `system.posix_acl_access` and
+    /// `system.posix_acl_default` extended attributes. Unix only.
+    pub acls: bool,
+}
+
+impl Preserve {
+    /// Preserves every supported attribute.
+    pub fn all() -> Self {
+        Preserve {
+            times: true,
+            permissions: true,
+            ownership: true,
+            xattrs: true,
+            acls: true,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !(self.times || self.permissions || self.ownership || self.xattrs || self.acls)
+    }
+}
+
+/// Applies the attributes selected by `preserve` from `source` to `dst`.
+pub(crate) fn apply_metadata(source: &Metadata, dst: &Path, preserve: Preserve) -> io::Result<()> {
+    // Ownership goes first: chown clears the setuid and setgid bits.
+    #[cfg(unix)]
+    {
+        if preserve.ownership && is_root() {
+            use std::os::unix::fs::MetadataExt;
+            std::os::unix::fs::lchown(dst, Some(source.uid()), Some(source.gid()))?;
+        }
+    }
+
+    if preserve.permissions {
+        fs::set_permissions(dst, source.permissions())?;
+    }
+
+    // The symlink variant sets times by path; the other opens the file,
+    // which blocks on a FIFO
+    if preserve.times {
+        filetime::set_symlink_file_times(
+            dst,
+            FileTime::from_last_access_time(source),
+            FileTime::from_last_modification_time(source),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Gives the owner full access to the directory `path` when it lacks it.
+///
+/// A directory whose read-only mode was preserved by an earlier run could
+/// not be written into otherwise; the caller sets the preserved mode again
+/// once it is done, as rsync does.
+pub(crate) fn make_writable(path: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = fs::metadata(path)?.permissions().mode();
+        if mode & 0o700 != 0o700 {
+            fs::set_permissions(path, fs::Permissions::from_mode(mode | 0o700))?;
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+#[cfg(unix)]
+pub(crate) fn is_root() -> bool {
+    // SAFETY: geteuid has no preconditions and cannot fail.
+    unsafe { libc::geteuid() == 0 }
+}
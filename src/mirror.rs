@@ -0,0 +1,151 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use walkdir::WalkDir;
+
+use crate::crypto::Cipher;
+use crate::job::Source;
+use crate::report::{BackupReport, FileAction, FileReport};
+
+/// Removes files and directories from the subtree of `target_dir` holding
+/// `source` that no longer exist in the source directory. Entries excluded
+/// by the source's filter are never deleted.
+///
+/// With a `cipher` that encrypts names, target paths are decrypted before
+/// they are compared; entries whose names do not decrypt are kept as they
+/// are.
+///
+/// Nothing is deleted if the candidates exceed `max_delete_percent` of the
+/// files in the subtree; the run is recorded as an error instead. With
+/// `dry_run` the candidates are only reported.
+pub(crate) fn delete_extraneous(
+    source: &mut Source,
+    target_dir: &Path,
+    cipher: Option<&Cipher>,
+    max_delete_percent: u8,
+    dry_run: bool,
+    report: &mut BackupReport,
+) {
+    let source_dir = source.dir.as_path();
+    let filter = &mut source.filter;
+    let target_dir = &match cipher {
+        Some(cipher) => match cipher.encrypt_path(&source.subtree) {
+            Ok(stored_subtree) => target_dir.join(stored_subtree),
+            Err(e) => {
+                report.errors.push(format!(
+                    "Error encrypting the name of {:?}: {}",
+                    source.subtree, e
+                ));
+                return;
+            }
+        },
+        None => target_dir.join(&source.subtree),
+    };
+    // A source added since the last run has nothing to remove yet
+    if !target_dir.is_dir() {
+        return;
+    }
+
+    let mut target_files = 0usize;
+    // Stored and source-side relative paths, and whether each is a directory
+    let mut candidates: Vec<(PathBuf, PathBuf, bool)> = Vec::new();
+    let plain_path = |stored_path: &Path| match cipher {
+        Some(cipher) => cipher.decrypt_path(stored_path).ok(),
+        None => Some(stored_path.to_path_buf()),
+    };
+
+    let walker = WalkDir::new(target_dir)
+        .min_depth(1)
+        .into_iter()
+        .filter_entry(|entry| {
+            let stored_path = entry
+                .path()
+                .strip_prefix(target_dir)
+                .unwrap_or(entry.path());
+            match plain_path(stored_path) {
+                Some(relative_path) => {
+                    !filter.is_excluded(&relative_path, entry.file_type().is_dir())
+                }
+                None => false,
+            }
+        });
+    for entry in walker {
+        let entry = match entry {
+            Ok(e) => e,
+            Err(e) => {
+                report
+                    .errors
+                    .push(format!("Error reading target entry: {}", e));
+                continue;
+            }
+        };
+
+        let is_dir = entry.file_type().is_dir();
+        if !is_dir {
+            target_files += 1;
+        }
+
+        let stored_path = match entry.path().strip_prefix(target_dir) {
+            Ok(p) => p,
+            Err(_) => continue,
+        };
+        let Some(relative_path) = plain_path(stored_path) else {
+            continue;
+        };
+
+        match fs::symlink_metadata(source_dir.join(&relative_path)) {
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                candidates.push((stored_path.to_path_buf(), relative_path, is_dir));
+            }
+            Err(e) => {
+                report.errors.push(format!(
+                    "Error checking {:?} in source: {}",
+                    relative_path, e
+                ));
+            }
+        }
+    }
+
+    let files_to_delete = candidates.iter().filter(|(_, _, is_dir)| !is_dir).count();
+    if target_files > 0 && files_to_delete * 100 > target_files * max_delete_percent as usize {
+        report.errors.push(format!(
+            "Refusing to delete {} of {} files in the target (limit is {}%).",
+            files_to_delete, target_files, max_delete_percent
+        ));
+        return;
+    }
+
+    // Children are removed before their parent so directories are empty by
+    // the time they are removed.
+    for (stored_path, relative_path, is_dir) in candidates.into_iter().rev() {
+        let mut file = FileReport {
+            path: source.target_path(&relative_path),
+            action: if dry_run {
+                FileAction::WouldDelete
+            } else {
+                FileAction::Deleted
+            },
+            bytes: 0,
+            error: None,
+        };
+
+        if !dry_run {
+            let path = target_dir.join(&stored_path);
+            let result = if is_dir {
+                fs::remove_dir(&path)
+            } else {
+                fs::remove_file(&path)
+            };
+            match result {
+                Ok(()) => {}
+                // The directory still holds excluded entries, so keep it
+                Err(e) if is_dir && e.kind() == io::ErrorKind::DirectoryNotEmpty => continue,
+                Err(e) => file.error = Some(format!("Error deleting {:?}: {}", file.path, e)),
+            }
+        }
+
+        report.files.push(file);
+    }
+}
@@ -0,0 +1,579 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use chrono::{Datelike, TimeDelta};
+use serde::{Deserialize, Deserializer};
+use walkdir::WalkDir;
+
+use crate::error::{Error, Result};
+use crate::manifest::Manifest;
+use crate::repository::Repository;
+use crate::snapshot::{is_snapshot_target, list_snapshots, Snapshot};
+
+/// Which snapshots to keep when pruning, in the style of restic and borg.
+///
+/// A snapshot is kept if any rule selects it; a rule set to 0 (or `None`)
+/// is disabled. The newest snapshot is always kept.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetentionPolicy {
+    /// Keep the N most recent snapshots.
+    pub keep_last: usize,
+    /// Keep the newest snapshot of each of the last N days with snapshots.
+    pub keep_daily: usize,
+    /// Keep the newest snapshot of each of the last N ISO weeks with
+    /// snapshots.
+    pub keep_weekly: usize,
+    /// Keep the newest snapshot of each of the last N months with
+    /// snapshots.
+    pub keep_monthly: usize,
+    /// Keep every snapshot taken within this long of the newest one.
+    /// Written like `7d` in a config file; see [`parse_duration`].
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub keep_within: Option<TimeDelta>,
+}
+
+impl RetentionPolicy {
+    pub fn is_empty(&self) -> bool {
+        self.keep_last == 0
+            && self.keep_daily == 0
+            && self.keep_weekly == 0
+            && self.keep_monthly == 0
+            && self.keep_within.is_none()
+    }
+}
+
+/// The decision taken for one snapshot.
+#[derive(Debug, Clone)]
+pub struct PruneEntry {
+    pub snapshot: Snapshot,
+    /// Rules that kept the snapshot, such as `"daily"`; empty if it was
+    /// removed.
+    pub reasons: Vec<&'static str>,
+    /// Set when the snapshot should have been removed but could not be.
+    pub error: Option<String>,
+}
+
+impl PruneEntry {
+    pub fn is_kept(&self) -> bool {
+        !self.reasons.is_empty()
+    }
+}
+
+/// Summary of a [`prune`] run.
+#[derive(Debug, Clone, Default)]
+pub struct PruneReport {
+    /// Every snapshot found, newest first.
+    pub entries: Vec<PruneEntry>,
+    /// Bytes of file data that are no longer referenced by any kept
+    /// snapshot.
+    pub freed_bytes: u64,
+    /// Set when snapshots were only selected, not removed.
+    pub dry_run: bool,
+    /// Problems not tied to one snapshot, such as a chunk of a repository
+    /// that could not be removed.
+    pub errors: Vec<String>,
+}
+
+impl PruneReport {
+    pub fn removed(&self) -> impl Iterator<Item = &PruneEntry> {
+        self.entries.iter().filter(|e| !e.is_kept())
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.errors.is_empty() && self.entries.iter().all(|e| e.error.is_none())
+    }
+}
+
+/// Removes the snapshots in `root` that `policy` does not keep. `root` must
+/// be a repository or a target written by snapshot runs.
+///
+/// Unchanged files are hard-linked between snapshots, so removing a
+/// snapshot only frees the data that no kept snapshot links to. In a chunk
+/// repository the snapshot indexes are removed, followed by every chunk no
+/// remaining snapshot refers to. With `dry_run` the snapshots are only
+/// selected and nothing is removed.
+pub fn prune(root: &Path, policy: &RetentionPolicy, dry_run: bool) -> Result<PruneReport> {
+    if policy.is_empty() {
+        return Err(Error::EmptyRetentionPolicy);
+    }
+    // Directories of a plain target are backed-up data, whatever their names
+    if !Repository::is_repository(root) && !is_snapshot_target(root) {
+        return Err(Error::NotSnapshotTarget(root.to_path_buf()));
+    }
+
+    let mut snapshots = list_snapshots(root).map_err(|e| Error::Snapshot(root.to_path_buf(), e))?;
+    snapshots.reverse();
+
+    let mut entries: Vec<PruneEntry> = snapshots
+        .into_iter()
+        .map(|snapshot| PruneEntry {
+            snapshot,
+            reasons: Vec::new(),
+            error: None,
+        })
+        .collect();
+
+    select(&mut entries, policy);
+
+    if Repository::is_repository(root) {
+        return prune_repository(root, entries, dry_run);
+    }
+
+    let removed: Vec<&Path> = entries
+        .iter()
+        .filter(|e| !e.is_kept())
+        .map(|e| e.snapshot.path.as_path())
+        .collect();
+    let freed_bytes = unreferenced_bytes(&removed);
+
+    if !dry_run {
+        for entry in entries.iter_mut().filter(|e| !e.is_kept()) {
+            if let Err(e) = fs::remove_dir_all(&entry.snapshot.path) {
+                entry.error = Some(format!("Error removing snapshot: {}", e));
+            }
+        }
+    }
+
+    Ok(PruneReport {
+        entries,
+        freed_bytes,
+        dry_run,
+        errors: Vec::new(),
+    })
+}
+
+/// Removes the index files of the snapshots that are not kept, then the
+/// chunks that no remaining snapshot refers to.
+fn prune_repository(
+    root: &Path,
+    mut entries: Vec<PruneEntry>,
+    dry_run: bool,
+) -> Result<PruneReport> {
+    let repository =
+        Repository::open(root).map_err(|e| Error::Repository(root.to_path_buf(), e))?;
+
+    if !dry_run {
+        for entry in entries.iter_mut().filter(|e| !e.is_kept()) {
+            if let Err(e) = fs::remove_file(&entry.snapshot.path) {
+                entry.error = Some(format!("Error removing snapshot: {}", e));
+            }
+        }
+    }
+
+    let mut report = PruneReport {
+        entries: Vec::new(),
+        freed_bytes: 0,
+        dry_run,
+        errors: Vec::new(),
+    };
+
+    // Snapshots that could not be removed still need their chunks
+    let mut indexes = Vec::new();
+    for entry in entries.iter().filter(|e| e.is_kept() || e.error.is_some()) {
+        match Manifest::read(&entry.snapshot.path, None) {
+            Ok(index) => indexes.push(index),
+            Err(e) => {
+                report.errors.push(format!(
+                    "Error reading snapshot {}, leaving all chunks in place: {}",
+                    entry.snapshot.name, e
+                ));
+                report.entries = entries;
+                return Ok(report);
+            }
+        }
+    }
+
+    match repository.unreferenced_chunks(&indexes) {
+        Ok(chunks) => {
+            for (path, len) in chunks {
+                if dry_run {
+                    report.freed_bytes += len;
+                    continue;
+                }
+                match fs::remove_file(&path) {
+                    Ok(()) => report.freed_bytes += len,
+                    Err(e) => report
+                        .errors
+                        .push(format!("Error removing chunk {:?}: {}", path, e)),
+                }
+            }
+        }
+        Err(e) => report.errors.push(format!("Error listing chunks: {}", e)),
+    }
+
+    report.entries = entries;
+    Ok(report)
+}
+
+/// Records in each entry which rules keep it. `entries` is newest first.
+fn select(entries: &mut [PruneEntry], policy: &RetentionPolicy) {
+    let Some(newest) = entries.first().map(|e| e.snapshot.time) else {
+        return;
+    };
+
+    for entry in entries.iter_mut().take(policy.keep_last) {
+        entry.reasons.push("last");
+    }
+
+    keep_per_period(entries, policy.keep_daily, "daily", |t| {
+        (t.year(), t.ordinal())
+    });
+    keep_per_period(entries, policy.keep_weekly, "weekly", |t| {
+        let week = t.iso_week();
+        (week.year(), week.week())
+    });
+    keep_per_period(entries, policy.keep_monthly, "monthly", |t| {
+        (t.year(), t.month())
+    });
+
+    if let Some(within) = policy.keep_within {
+        for entry in entries.iter_mut() {
+            if newest - entry.snapshot.time <= within {
+                entry.reasons.push("within");
+            }
+        }
+    }
+
+    if entries[0].reasons.is_empty() {
+        entries[0].reasons.push("newest");
+    }
+}
+
+/// Keeps the newest snapshot of each of the `count` most recent periods,
+/// where `period` maps a snapshot time to its period.
+fn keep_per_period<K: PartialEq>(
+    entries: &mut [PruneEntry],
+    count: usize,
+    reason: &'static str,
+    period: impl Fn(&chrono::NaiveDateTime) -> K,
+) {
+    let mut kept = 0;
+    let mut last = None;
+    for entry in entries.iter_mut() {
+        if kept == count {
+            break;
+        }
+        let key = period(&entry.snapshot.time);
+        if last.as_ref() != Some(&key) {
+            entry.reasons.push(reason);
+            kept += 1;
+            last = Some(key);
+        }
+    }
+}
+
+/// Sums the size of the files whose every hard link lies inside `dirs`.
+fn unreferenced_bytes(dirs: &[&Path]) -> u64 {
+    // (device, inode) -> (links found, total links, size)
+    let mut links: HashMap<(u64, u64), (u64, u64, u64)> = HashMap::new();
+
+    for dir in dirs {
+        for entry in WalkDir::new(dir).into_iter().filter_map(|e| e.ok()) {
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+
+            #[cfg(unix)]
+            let (key, nlink) = {
+                use std::os::unix::fs::MetadataExt;
+                ((metadata.dev(), metadata.ino()), metadata.nlink())
+            };
+            #[cfg(not(unix))]
+            let (key, nlink) = ((0, links.len() as u64), 1);
+
+            let seen = links.entry(key).or_insert((0, nlink, metadata.len()));
+            seen.0 += 1;
+        }
+    }
+
+    links
+        .into_values()
+        .filter(|(found, nlink, _)| found >= nlink)
+        .map(|(_, _, len)| len)
+        .sum()
+}
+
+/// Parses a duration such as `7d`, `2w`, `6m` or `1y2m` for
+/// [`RetentionPolicy::keep_within`].
+///
+/// Units are `h` (hours), `d` (days), `w` (weeks), `m` (30-day months)
+/// and `y` (365-day years).
+pub fn parse_duration(text: &str) -> std::result::Result<TimeDelta, String> {
+    let mut total = TimeDelta::zero();
+    let mut number = String::new();
+    for c in text.trim().chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let value: i64 = number
+            .parse()
+            .map_err(|_| format!("expected a number before '{}' in {:?}", c, text))?;
+        number.clear();
+        let hours = match c {
+            'h' => 1,
+            'd' => 24,
+            'w' => 24 * 7,
+            'm' => 24 * 30,
+            'y' => 24 * 365,
+            _ => return Err(format!("unknown unit '{}' in {:?}", c, text)),
+        };
+        total = value
+            .checked_mul(hours)
+            .and_then(TimeDelta::try_hours)
+            .and_then(|delta| total.checked_add(&delta))
+            .ok_or_else(|| format!("duration {:?} is too long", text))?;
+    }
+    if !number.is_empty() || total.is_zero() {
+        return Err(format!(
+            "invalid duration {:?}, expected e.g. 7d, 2w or 1y6m",
+            text
+        ));
+    }
+    Ok(total)
+}
+
+/// Reads an optional duration in the format taken by [`parse_duration`].
+fn deserialize_duration<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Option<TimeDelta>, D::Error> {
+    match Option::<String>::deserialize(deserializer)? {
+        Some(text) => parse_duration(&text)
+            .map(Some)
+            .map_err(serde::de::Error::custom),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    use crate::testing::scratch;
+
+    use chrono::NaiveDateTime;
+
+    /// Entries for snapshots taken at `times`, newest first.
+    fn entries(times: &[&str]) -> Vec<PruneEntry> {
+        times
+            .iter()
+            .map(|time| PruneEntry {
+                snapshot: Snapshot {
+                    name: time.to_string(),
+                    path: PathBuf::from(time),
+                    time: NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M").unwrap(),
+                },
+                reasons: Vec::new(),
+                error: None,
+            })
+            .collect()
+    }
+
+    fn kept(entries: &[PruneEntry]) -> Vec<&str> {
+        entries
+            .iter()
+            .filter(|e| e.is_kept())
+            .map(|e| e.snapshot.name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn daily_keeps_the_newest_snapshot_of_each_day() {
+        let mut entries = entries(&[
+            "2026-10-17 18:00",
+            "2026-10-17 06:00",
+            "2026-10-16 18:00",
+            "2026-10-14 12:00",
+            "2026-10-14 08:00",
+            "2026-10-13 12:00",
+        ]);
+        let policy = RetentionPolicy {
+            keep_daily: 3,
+            ..Default::default()
+        };
+        select(&mut entries, &policy);
+        assert_eq!(
+            kept(&entries),
+            ["2026-10-17 18:00", "2026-10-16 18:00", "2026-10-14 12:00"]
+        );
+        assert_eq!(entries[0].reasons, ["daily"]);
+    }
+
+    #[test]
+    fn weekly_uses_iso_weeks() {
+        // 2026-10-12 is a Monday, so 2026-10-11 ends the week that started
+        // on 2026-10-05
+        let mut entries = entries(&[
+            "2026-10-13 12:00",
+            "2026-10-12 00:30",
+            "2026-10-11 23:30",
+            "2026-10-05 12:00",
+            "2026-09-28 12:00",
+        ]);
+        let policy = RetentionPolicy {
+            keep_weekly: 3,
+            ..Default::default()
+        };
+        select(&mut entries, &policy);
+        assert_eq!(
+            kept(&entries),
+            ["2026-10-13 12:00", "2026-10-11 23:30", "2026-09-28 12:00"]
+        );
+    }
+
+    #[test]
+    fn monthly_skips_months_without_snapshots() {
+        let mut entries = entries(&[
+            "2026-10-02 12:00",
+            "2026-10-01 12:00",
+            "2026-08-31 12:00",
+            "2026-08-01 12:00",
+            "2025-12-31 12:00",
+            "2025-11-30 12:00",
+        ]);
+        let policy = RetentionPolicy {
+            keep_monthly: 3,
+            ..Default::default()
+        };
+        select(&mut entries, &policy);
+        assert_eq!(
+            kept(&entries),
+            ["2026-10-02 12:00", "2026-08-31 12:00", "2025-12-31 12:00"]
+        );
+    }
+
+    #[test]
+    fn rules_combine_and_record_their_reasons() {
+        let mut entries = entries(&[
+            "2026-10-17 18:00",
+            "2026-10-17 06:00",
+            "2026-10-16 18:00",
+            "2026-10-01 12:00",
+            "2026-09-15 12:00",
+        ]);
+        let policy = RetentionPolicy {
+            keep_last: 2,
+            keep_monthly: 2,
+            keep_within: Some(TimeDelta::days(1)),
+            ..Default::default()
+        };
+        select(&mut entries, &policy);
+        assert_eq!(entries[0].reasons, ["last", "monthly", "within"]);
+        assert_eq!(entries[1].reasons, ["last", "within"]);
+        assert_eq!(entries[2].reasons, ["within"]);
+        assert!(!entries[3].is_kept());
+        assert_eq!(entries[4].reasons, ["monthly"]);
+    }
+
+    #[test]
+    fn the_newest_snapshot_is_always_kept() {
+        let mut entries = entries(&["2026-10-17 18:00", "2020-01-01 00:00"]);
+        let policy = RetentionPolicy {
+            keep_within: Some(TimeDelta::hours(1)),
+            ..Default::default()
+        };
+        // Only the newest is within an hour of itself
+        select(&mut entries, &policy);
+        assert_eq!(kept(&entries), ["2026-10-17 18:00"]);
+
+        // A rule that keeps nothing still leaves the newest
+        let mut entries = self::entries(&["2026-10-17 18:00", "2026-10-16 18:00"]);
+        keep_per_period(&mut entries, 0, "daily", |t| t.ordinal());
+        assert!(entries.iter().all(|e| !e.is_kept()));
+        select(&mut entries, &RetentionPolicy::default());
+        assert_eq!(entries[0].reasons, ["newest"]);
+        assert!(!entries[1].is_kept());
+    }
+
+    #[test]
+    fn keep_per_period_stops_after_count_periods() {
+        let mut entries = entries(&[
+            "2026-10-17 18:00",
+            "2026-10-17 06:00",
+            "2026-10-16 18:00",
+            "2026-10-15 18:00",
+        ]);
+        keep_per_period(&mut entries, 2, "daily", |t| t.ordinal());
+        assert_eq!(kept(&entries), ["2026-10-17 18:00", "2026-10-16 18:00"]);
+    }
+
+    #[test]
+    fn plain_targets_are_not_pruned() {
+        let dir = scratch("prune-plain");
+        let source = dir.join("src");
+        fs::create_dir_all(source.join("2020-01-01T00-00-00")).unwrap();
+        fs::write(source.join("2020-01-01T00-00-00/data"), "data").unwrap();
+        let target = dir.join("out");
+        crate::BackupJob::new(&source, &target).run().unwrap();
+
+        let policy = RetentionPolicy {
+            keep_last: 1,
+            ..Default::default()
+        };
+        assert!(matches!(
+            prune(&target, &policy, false),
+            Err(Error::NotSnapshotTarget(_))
+        ));
+        assert!(target.join("2020-01-01T00-00-00/data").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn only_directories_with_a_manifest_are_snapshots() {
+        let dir = scratch("prune-snapshots");
+        for name in ["2026-10-16T00-00-00", "2026-10-17T00-00-00"] {
+            Manifest::new().save(&dir.join(name)).unwrap();
+        }
+        fs::create_dir(dir.join("2020-01-01T00-00-00")).unwrap();
+        std::os::unix::fs::symlink("2026-10-17T00-00-00", dir.join("latest")).unwrap();
+
+        let policy = RetentionPolicy {
+            keep_last: 1,
+            ..Default::default()
+        };
+        let report = prune(&dir, &policy, false).unwrap();
+        assert_eq!(report.entries.len(), 2);
+        assert!(!dir.join("2026-10-16T00-00-00").exists());
+        assert!(dir.join("2026-10-17T00-00-00").exists());
+        assert!(dir.join("2020-01-01T00-00-00").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn durations_add_up_their_units() {
+        assert_eq!(parse_duration("12h"), Ok(TimeDelta::hours(12)));
+        assert_eq!(parse_duration("7d"), Ok(TimeDelta::days(7)));
+        assert_eq!(parse_duration("2w"), Ok(TimeDelta::days(14)));
+        assert_eq!(parse_duration("6m"), Ok(TimeDelta::days(180)));
+        assert_eq!(parse_duration(" 1y2m "), Ok(TimeDelta::days(365 + 60)));
+        assert_eq!(parse_duration("1d12h"), Ok(TimeDelta::hours(36)));
+    }
+
+    #[test]
+    fn invalid_durations_are_refused() {
+        for text in ["", "7", "d", "7x", "0d", "-1d", "1.5d", "7d3"] {
+            assert!(parse_duration(text).is_err(), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn overlong_durations_are_refused() {
+        for text in [
+            "3000000000000h",
+            "99999999999999999999h",
+            "9223372036854775807y",
+            "5000000000000h5000000000000h",
+        ] {
+            assert!(parse_duration(text).is_err(), "{:?}", text);
+        }
+    }
+}
@@ -0,0 +1,255 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Serialize, Serializer};
+
+/// What the backup decided to do with a source entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileAction {
+    /// The file does not exist in the target yet.
+    New,
+    /// The source file is newer than the copy in the target.
+    Modified,
+    /// The copy in the target is up to date.
+    Skipped,
+    /// The entry exists only in the target and was removed by mirror mode.
+    Deleted,
+    /// The entry exists only in the target and would be removed by mirror
+    /// mode, but the run only previewed deletions.
+    #[serde(rename = "would_delete")]
+    WouldDelete,
+    /// The entry could not be inspected, so no action was chosen.
+    Failed,
+}
+
+impl fmt::Display for FileAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            FileAction::New => "new",
+            FileAction::Modified => "modified",
+            FileAction::Skipped => "skipped",
+            FileAction::Deleted => "deleted",
+            FileAction::WouldDelete => "would delete",
+            FileAction::Failed => "failed",
+        };
+        f.pad(name)
+    }
+}
+
+/// The outcome for a single entry of the source tree.
+#[derive(Debug, Clone, Serialize)]
+pub struct FileReport {
+    /// Path relative to the source directory, or to the target directory
+    /// for deleted entries.
+    #[serde(serialize_with = "serialize_path")]
+    pub path: PathBuf,
+    pub action: FileAction,
+    /// Number of bytes written to the target.
+    pub bytes: u64,
+    /// Set when the entry could not be backed up.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl FileReport {
+    /// Creates the entry of `path` when it could not be backed up.
+    pub fn failed(path: impl Into<PathBuf>, error: impl Into<String>) -> Self {
+        FileReport {
+            path: path.into(),
+            action: FileAction::Failed,
+            bytes: 0,
+            error: Some(error.into()),
+        }
+    }
+
+    /// Creates the entry of `path` before it is processed, counted as
+    /// failed until its action is set.
+    pub(crate) fn pending(path: impl Into<PathBuf>) -> Self {
+        FileReport {
+            path: path.into(),
+            action: FileAction::Failed,
+            bytes: 0,
+            error: None,
+        }
+    }
+
+    pub fn is_failed(&self) -> bool {
+        self.error.is_some()
+    }
+}
+
+/// Summary of a finished [`BackupJob`](crate::BackupJob) run.
+///
+/// Serializes to a document with the run's times, directories, counters and
+/// one entry per file; see [`to_json`](Self::to_json).
+#[derive(Debug, Clone)]
+pub struct BackupReport {
+    /// The first of the [`sources`](Self::sources), for reports of runs
+    /// with a single source.
+    pub source: PathBuf,
+    /// Source directories, each backed up into its own subtree of the
+    /// target when there are several.
+    pub sources: Vec<PathBuf>,
+    pub target: PathBuf,
+    pub started: SystemTime,
+    pub finished: SystemTime,
+    /// Name of the snapshot written by a snapshot run.
+    pub snapshot: Option<String>,
+    /// One entry per file found in the source, in scan order.
+    pub files: Vec<FileReport>,
+    /// Problems that are not tied to a single entry.
+    pub errors: Vec<String>,
+    /// Set when the run only planned its actions and left the target
+    /// untouched.
+    pub dry_run: bool,
+}
+
+impl BackupReport {
+    /// Creates an empty report for a run starting now.
+    pub fn new(source: &Path, target: &Path) -> Self {
+        let now = SystemTime::now();
+        BackupReport {
+            source: source.to_path_buf(),
+            sources: vec![source.to_path_buf()],
+            target: target.to_path_buf(),
+            started: now,
+            finished: now,
+            snapshot: None,
+            files: Vec::new(),
+            errors: Vec::new(),
+            dry_run: false,
+        }
+    }
+
+    /// Number of files found in the source.
+    pub fn scanned(&self) -> usize {
+        self.files
+            .iter()
+            .filter(|f| !matches!(f.action, FileAction::Deleted | FileAction::WouldDelete))
+            .count()
+    }
+
+    /// Number of files that were copied to the target, or would be copied
+    /// in a dry run.
+    pub fn copied(&self) -> usize {
+        self.files
+            .iter()
+            .filter(|f| {
+                !f.is_failed() && matches!(f.action, FileAction::New | FileAction::Modified)
+            })
+            .count()
+    }
+
+    /// Number of files that were already up to date.
+    pub fn skipped(&self) -> usize {
+        self.files
+            .iter()
+            .filter(|f| !f.is_failed() && f.action == FileAction::Skipped)
+            .count()
+    }
+
+    /// Number of target entries removed by mirror mode.
+    pub fn deleted(&self) -> usize {
+        self.files
+            .iter()
+            .filter(|f| !f.is_failed() && f.action == FileAction::Deleted)
+            .count()
+    }
+
+    /// Number of target entries mirror mode would have removed in a run
+    /// that only previewed deletions.
+    pub fn would_delete(&self) -> usize {
+        self.files
+            .iter()
+            .filter(|f| f.action == FileAction::WouldDelete)
+            .count()
+    }
+
+    /// Number of files that could not be backed up.
+    pub fn failed(&self) -> usize {
+        self.failures().count()
+    }
+
+    /// Returns true when no entry failed and no other error occurred.
+    pub fn is_success(&self) -> bool {
+        self.errors.is_empty() && self.failed() == 0
+    }
+
+    /// Total number of bytes written to the target.
+    pub fn bytes_copied(&self) -> u64 {
+        self.files.iter().map(|f| f.bytes).sum()
+    }
+
+    /// Files that could not be backed up.
+    pub fn failures(&self) -> impl Iterator<Item = &FileReport> {
+        self.files.iter().filter(|f| f.is_failed())
+    }
+
+    /// Renders the report as a pretty-printed JSON document.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Failed to serialize backup report")
+    }
+}
+
+impl Serialize for BackupReport {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct Document<'a> {
+            started: String,
+            finished: String,
+            source: String,
+            sources: Vec<String>,
+            target: String,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            snapshot: Option<&'a str>,
+            dry_run: bool,
+            scanned: usize,
+            copied: usize,
+            skipped: usize,
+            deleted: usize,
+            would_delete: usize,
+            failed: usize,
+            bytes_copied: u64,
+            files: &'a [FileReport],
+            errors: &'a [String],
+        }
+
+        Document {
+            started: format_time(self.started),
+            finished: format_time(self.finished),
+            source: self.source.to_string_lossy().into_owned(),
+            sources: self
+                .sources
+                .iter()
+                .map(|source| source.to_string_lossy().into_owned())
+                .collect(),
+            target: self.target.to_string_lossy().into_owned(),
+            snapshot: self.snapshot.as_deref(),
+            dry_run: self.dry_run,
+            scanned: self.scanned(),
+            copied: self.copied(),
+            skipped: self.skipped(),
+            deleted: self.deleted(),
+            would_delete: self.would_delete(),
+            // Counted as the text summary counts them
+            failed: self.failed() + self.errors.len(),
+            bytes_copied: self.bytes_copied(),
+            files: &self.files,
+            errors: &self.errors,
+        }
+        .serialize(serializer)
+    }
+}
+
+/// Formats a timestamp as RFC 3339 in UTC, e.g. `2026-10-17T02:00:00Z`.
+fn format_time(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time).to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// Serializes paths lossily so that non-UTF-8 names cannot fail a report.
+fn serialize_path<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&path.to_string_lossy())
+}
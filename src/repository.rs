@@ -0,0 +1,329 @@
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use fastcdc::v2020::StreamCDC;
+use indicatif::MultiProgress;
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+
+use crate::copy::{file_progress_bar, temp_path};
+use crate::manifest::{Manifest, ManifestEntry};
+
+/// File marking a directory as a chunk repository and holding its settings.
+pub const REPOSITORY_FILE: &str = "srb-repository.json";
+
+const CHUNKS_DIR: &str = "chunks";
+const SNAPSHOTS_DIR: &str = "snapshots";
+const REPOSITORY_VERSION: u32 = 1;
+
+/// Counter keeping the temporary names of chunks written in parallel apart.
+static NEXT_TEMP: AtomicUsize = AtomicUsize::new(0);
+
+/// Settings fixed when a repository is created.
+///
+/// The chunk sizes must not change afterwards, or unchanged data would be
+/// cut differently and no longer match the stored chunks.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Config {
+    version: u32,
+    min_chunk_size: u32,
+    avg_chunk_size: u32,
+    max_chunk_size: u32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            version: REPOSITORY_VERSION,
+            min_chunk_size: 512 * 1024,
+            avg_chunk_size: 1024 * 1024,
+            max_chunk_size: 8 * 1024 * 1024,
+        }
+    }
+}
+
+/// A deduplicating store of file contents.
+///
+/// Files are cut into content-defined chunks with FastCDC, so an edit only
+/// changes the chunks around it. Each distinct chunk is stored once under
+/// `chunks/`, named by its BLAKE3 hash, and every snapshot is an index file
+/// in `snapshots/` listing the chunks of each file.
+#[derive(Debug)]
+pub(crate) struct Repository {
+    root: PathBuf,
+    config: Config,
+}
+
+/// The outcome of storing one file in a repository.
+pub(crate) struct StoredFile {
+    pub(crate) size: u64,
+    /// Bytes of chunks the repository did not hold yet.
+    pub(crate) new_bytes: u64,
+    pub(crate) hash: blake3::Hash,
+    pub(crate) chunks: Vec<String>,
+}
+
+impl Repository {
+    /// Returns whether `root` holds a repository.
+    pub(crate) fn is_repository(root: &Path) -> bool {
+        root.join(REPOSITORY_FILE).is_file()
+    }
+
+    pub(crate) fn open(root: &Path) -> io::Result<Repository> {
+        let file = File::open(root.join(REPOSITORY_FILE))?;
+        let config: Config = serde_json::from_reader(BufReader::new(file))?;
+        if config.version != REPOSITORY_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported repository version {}", config.version),
+            ));
+        }
+        Ok(Repository {
+            root: root.to_path_buf(),
+            config,
+        })
+    }
+
+    /// Opens the repository at `root`, creating it first when `root` is
+    /// missing or empty. With `dry_run` a new repository is not written.
+    pub(crate) fn open_or_create(root: &Path, dry_run: bool) -> io::Result<Repository> {
+        if Self::is_repository(root) {
+            return Self::open(root);
+        }
+        if root.is_dir() && fs::read_dir(root)?.next().is_some() {
+            return Err(io::Error::other(
+                "the directory is not empty and does not hold a repository",
+            ));
+        }
+
+        let repository = Repository {
+            root: root.to_path_buf(),
+            config: Config::default(),
+        };
+        if !dry_run {
+            fs::create_dir_all(root.join(CHUNKS_DIR))?;
+            fs::create_dir_all(root.join(SNAPSHOTS_DIR))?;
+            let config = serde_json::to_vec_pretty(&repository.config)?;
+            fs::write(root.join(REPOSITORY_FILE), config)?;
+        }
+        Ok(repository)
+    }
+
+    /// Directory holding the snapshot indexes of the repository at `root`.
+    pub(crate) fn snapshots_dir(root: &Path) -> PathBuf {
+        root.join(SNAPSHOTS_DIR)
+    }
+
+    /// Path of the index of the snapshot called `name`.
+    pub(crate) fn index_path(&self, name: &str) -> PathBuf {
+        Self::snapshots_dir(&self.root).join(format!("{}.json", name))
+    }
+
+    fn chunk_path(&self, hash: &str) -> PathBuf {
+        let fan_out = hash.get(..2).unwrap_or_default();
+        self.root.join(CHUNKS_DIR).join(fan_out).join(hash)
+    }
+
+    /// Cuts `src` into chunks and stores the ones the repository does not
+    /// hold yet, showing a per-file progress bar.
+    pub(crate) fn store_file(
+        &self,
+        src: &Path,
+        relative_path: &Path,
+        mp: &MultiProgress,
+    ) -> io::Result<StoredFile> {
+        let file = File::open(src)?;
+        let pb = file_progress_bar(mp, file.metadata()?.len(), "Storing", relative_path);
+
+        let mut stored = StoredFile {
+            size: 0,
+            new_bytes: 0,
+            hash: blake3::Hash::from_bytes([0; 32]),
+            chunks: Vec::new(),
+        };
+        let mut hasher = blake3::Hasher::new();
+        let chunker = StreamCDC::new(
+            file,
+            self.config.min_chunk_size,
+            self.config.avg_chunk_size,
+            self.config.max_chunk_size,
+        );
+        for chunk in chunker {
+            let chunk = chunk?;
+            hasher.update(&chunk.data);
+            let hash = blake3::hash(&chunk.data).to_hex().to_string();
+            if self.write_chunk(&hash, &chunk.data)? {
+                stored.new_bytes += chunk.length as u64;
+            }
+            stored.size += chunk.length as u64;
+            stored.chunks.push(hash);
+            pb.inc(chunk.length as u64);
+        }
+        stored.hash = hasher.finalize();
+
+        pb.finish_and_clear();
+        Ok(stored)
+    }
+
+    /// Writes a chunk unless the repository already holds it, and returns
+    /// whether it was written.
+    fn write_chunk(&self, hash: &str, data: &[u8]) -> io::Result<bool> {
+        let path = self.chunk_path(hash);
+        if path.exists() {
+            return Ok(false);
+        }
+        let dir = path.parent().unwrap_or(&self.root);
+        fs::create_dir_all(dir)?;
+
+        // Workers may store the same chunk at the same time, so each one
+        // writes its own temporary file
+        let tmp = dir.join(format!(
+            ".{}.{}-{}.srb-tmp",
+            hash,
+            std::process::id(),
+            NEXT_TEMP.fetch_add(1, Ordering::Relaxed)
+        ));
+        let result = File::create(&tmp)
+            .and_then(|mut file| {
+                file.write_all(data)?;
+                file.sync_all()
+            })
+            .and_then(|_| fs::rename(&tmp, &path));
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp);
+        }
+        result.map(|_| true)
+    }
+
+    /// Reads a chunk, checking its contents against its hash.
+    fn read_chunk(&self, hash: &str) -> io::Result<Vec<u8>> {
+        let data = fs::read(self.chunk_path(hash)).map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => {
+                io::Error::new(e.kind(), format!("chunk {} is missing", hash))
+            }
+            _ => e,
+        })?;
+        if blake3::hash(&data).to_hex().as_str() != hash {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("chunk {} is damaged", hash),
+            ));
+        }
+        Ok(data)
+    }
+
+    /// Reassembles the file described by `entry` into `dst`.
+    ///
+    /// Like [`copy_with_progress`](crate::copy::copy_with_progress) the data
+    /// goes to a temporary file first, which only replaces `dst` once its
+    /// hash has been checked.
+    pub(crate) fn restore_file(
+        &self,
+        entry: &ManifestEntry,
+        dst: &Path,
+        relative_path: &Path,
+        mp: &MultiProgress,
+    ) -> io::Result<u64> {
+        let tmp = temp_path(dst);
+        let result = self
+            .write_file(entry, &tmp, relative_path, mp)
+            .and_then(|written| fs::rename(&tmp, dst).map(|_| written));
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp);
+        }
+        result
+    }
+
+    fn write_file(
+        &self,
+        entry: &ManifestEntry,
+        tmp: &Path,
+        relative_path: &Path,
+        mp: &MultiProgress,
+    ) -> io::Result<u64> {
+        let pb = file_progress_bar(mp, entry.size, "Restoring", relative_path);
+        let mut file = File::create(tmp)?;
+        let mut hasher = blake3::Hasher::new();
+        let mut written = 0;
+        for hash in &entry.chunks {
+            let data = self.read_chunk(hash)?;
+            file.write_all(&data)?;
+            hasher.update(&data);
+            written += data.len() as u64;
+            pb.inc(data.len() as u64);
+        }
+        file.sync_all()?;
+        pb.finish_and_clear();
+
+        if hasher.finalize().to_hex().as_str() != entry.hash {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "restored data does not match the hash in the index",
+            ));
+        }
+        Ok(written)
+    }
+
+    /// Checks that every chunk of the file described by `entry` is present
+    /// and intact, returning a description of the first problem found.
+    pub(crate) fn check_file(&self, entry: &ManifestEntry) -> io::Result<Option<String>> {
+        let mut hasher = blake3::Hasher::new();
+        let mut size = 0;
+        for hash in &entry.chunks {
+            match self.read_chunk(hash) {
+                Ok(data) => {
+                    hasher.update(&data);
+                    size += data.len() as u64;
+                }
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::NotFound | io::ErrorKind::InvalidData
+                    ) =>
+                {
+                    return Ok(Some(e.to_string()))
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        if size != entry.size {
+            return Ok(Some(format!(
+                "size {} in index, {} in chunks",
+                entry.size, size
+            )));
+        }
+        if hasher.finalize().to_hex().as_str() != entry.hash {
+            return Ok(Some("content differs from index".to_string()));
+        }
+        Ok(None)
+    }
+
+    /// Lists the stored chunks, and leftovers of interrupted writes, that
+    /// none of `indexes` refers to, with their sizes.
+    pub(crate) fn unreferenced_chunks(
+        &self,
+        indexes: &[Manifest],
+    ) -> io::Result<Vec<(PathBuf, u64)>> {
+        let used: HashSet<&str> = indexes
+            .iter()
+            .flat_map(|index| index.files.values())
+            .flat_map(|entry| entry.chunks.iter().map(String::as_str))
+            .collect();
+
+        let mut unused = Vec::new();
+        for entry in WalkDir::new(self.root.join(CHUNKS_DIR)).min_depth(2) {
+            let entry = entry?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            if !used.contains(entry.file_name().to_string_lossy().as_ref()) {
+                unused.push((entry.path().to_path_buf(), entry.metadata()?.len()));
+            }
+        }
+        Ok(unused)
+    }
+}
@@ -0,0 +1,747 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use filetime::FileTime;
+use indicatif::MultiProgress;
+use walkdir::WalkDir;
+
+use crate::copy::{copy_with_progress, progress_bars, replace_with, Transform};
+use crate::crypto::{unlock, Cipher, EncryptionKey};
+use crate::error::{Error, Result};
+use crate::filter::Filter;
+use crate::manifest::{key, set_mode, Manifest, ManifestEntry};
+use crate::metadata::{make_writable, Preserve};
+use crate::report::{BackupReport, FileAction, FileReport};
+use crate::repository::Repository;
+use crate::snapshot::resolve_backup;
+use crate::special::{can_create, device_number, hard_link_id, make_node, special_kind};
+use crate::symlink::replace_with_symlink;
+use crate::xattrs::{apply_attributes, read_xattrs, set_xattrs, supports_xattrs};
+
+/// Copies files from a backup back into a destination directory.
+///
+/// The backup may be a plain target directory or one holding snapshots, in
+/// which case the newest snapshot is restored unless another one is chosen.
+/// Chunk repositories are recognised and their files reassembled.
+///
+/// When the backup has a manifest, restored files get the modification
+/// time, permissions and any extended attributes recorded there, and their
+/// contents are checked against the recorded hash. Compressed files are
+/// decompressed, and an encrypted backup needs the key it was made with.
+/// Symbolic links are recreated as links, and directories, including empty
+/// ones, get their attributes once their contents are restored. FIFOs,
+/// sockets and, when running as root, device nodes are recreated, and files
+/// recorded as hard links of another restored file are linked to it.
+#[derive(Debug, Clone)]
+pub struct RestoreJob {
+    backup: PathBuf,
+    destination: PathBuf,
+    snapshot: Option<String>,
+    include: Vec<String>,
+    exclude: Vec<String>,
+    force: bool,
+    dry_run: bool,
+    preserve: Preserve,
+    progress: bool,
+    key: Option<EncryptionKey>,
+    /// Set when the destination cannot hold extended attributes.
+    xattrs_unsupported: bool,
+}
+
+impl RestoreJob {
+    /// Creates a job restoring the backup at `backup` into `destination`.
+    pub fn new(backup: impl Into<PathBuf>, destination: impl Into<PathBuf>) -> Self {
+        RestoreJob {
+            backup: backup.into(),
+            destination: destination.into(),
+            snapshot: None,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            force: false,
+            dry_run: false,
+            preserve: Preserve::all(),
+            progress: false,
+            key: None,
+            xattrs_unsupported: false,
+        }
+    }
+
+    /// Restores the snapshot with this id instead of the newest one.
+    pub fn snapshot(mut self, name: impl Into<String>) -> Self {
+        self.snapshot = Some(name.into());
+        self
+    }
+
+    /// Adds a glob of files to restore; see [`BackupJob::include`].
+    ///
+    /// [`BackupJob::include`]: crate::BackupJob::include
+    pub fn include(mut self, pattern: impl Into<String>) -> Self {
+        self.include.push(pattern.into());
+        self
+    }
+
+    /// Adds a glob of files and directories not to restore.
+    pub fn exclude(mut self, pattern: impl Into<String>) -> Self {
+        self.exclude.push(pattern.into());
+        self
+    }
+
+    /// Overwrites destination files even when they are newer than the
+    /// backup copy.
+    pub fn force(mut self, enabled: bool) -> Self {
+        self.force = enabled;
+        self
+    }
+
+    /// Reports what would be restored without writing anything.
+    pub fn dry_run(mut self, enabled: bool) -> Self {
+        self.dry_run = enabled;
+        self
+    }
+
+    /// Selects which attributes of the backup copies are applied to
+    /// restored files. Defaults to [`Preserve::all`].
+    pub fn preserve(mut self, preserve: Preserve) -> Self {
+        self.preserve = preserve;
+        self
+    }
+
+    /// Draws progress bars on the terminal while the job runs.
+    pub fn progress(mut self, enabled: bool) -> Self {
+        self.progress = enabled;
+        self
+    }
+
+    /// Decrypts an encrypted backup with `key`, the passphrase or key file
+    /// it was made with.
+    pub fn key(mut self, key: EncryptionKey) -> Self {
+        self.key = Some(key);
+        self
+    }
+
+    /// Runs the restore.
+    ///
+    /// In the returned report, `sources` holds the directory restored from and
+    /// `target` the destination.
+    pub fn run(&self) -> Result<BackupReport> {
+        let started = SystemTime::now();
+
+        if !self.backup.is_dir() {
+            return Err(Error::SourceNotDir(self.backup.clone()));
+        }
+
+        // Everything else is restored onto a file system without extended
+        // attributes
+        if (self.preserve.xattrs || self.preserve.acls)
+            && !self.dry_run
+            && !supports_xattrs(&self.destination)
+        {
+            let job = RestoreJob {
+                preserve: Preserve {
+                    xattrs: false,
+                    acls: false,
+                    ..self.preserve
+                },
+                xattrs_unsupported: true,
+                ..self.clone()
+            };
+            return job.run();
+        }
+
+        let (backup_dir, snapshot) = resolve_backup(&self.backup, self.snapshot.as_deref())?;
+
+        let mut filter =
+            Filter::new(&backup_dir, &self.include, &self.exclude)?.without_ignore_files();
+
+        if self.destination.exists() {
+            if !self.destination.is_dir() {
+                return Err(Error::TargetNotDir(self.destination.clone()));
+            }
+        } else if !self.dry_run {
+            fs::create_dir_all(&self.destination)
+                .map_err(|e| Error::CreateTarget(self.destination.clone(), e))?;
+        }
+
+        let mut report = BackupReport::new(&backup_dir, &self.destination);
+        report.started = started;
+        report.dry_run = self.dry_run;
+        report.snapshot = snapshot;
+
+        let repository = if Repository::is_repository(&self.backup) {
+            if report.snapshot.is_none() {
+                return Err(Error::Snapshot(
+                    self.backup.clone(),
+                    io::Error::new(io::ErrorKind::NotFound, "the repository has no snapshots"),
+                ));
+            }
+            Some(
+                Repository::open(&self.backup)
+                    .map_err(|e| Error::Repository(self.backup.clone(), e))?,
+            )
+        } else {
+            None
+        };
+
+        let cipher = unlock(&self.backup, self.key.as_ref())?;
+
+        // Without its manifest, an encrypted backup cannot be restored
+        let manifest = if repository.is_some() {
+            Some(
+                Manifest::read(&backup_dir, None)
+                    .map_err(|e| Error::Manifest(backup_dir.clone(), e))?,
+            )
+        } else if cipher.is_some() {
+            let path = Manifest::path(&backup_dir);
+            Some(Manifest::read(&path, cipher.as_ref()).map_err(|e| Error::Manifest(path, e))?)
+        } else {
+            match Manifest::load(&backup_dir) {
+                Ok(manifest) => manifest,
+                Err(e) => {
+                    report.errors.push(format!(
+                        "Error reading manifest {:?}, restoring without it: {}",
+                        Manifest::path(&backup_dir),
+                        e
+                    ));
+                    None
+                }
+            }
+        };
+
+        if self.xattrs_unsupported
+            && manifest
+                .iter()
+                .flat_map(|manifest| manifest.files.values())
+                .any(|entry| !entry.xattrs.is_empty())
+        {
+            report.errors.push(format!(
+                "{:?} does not support extended attributes; they are not restored",
+                self.destination
+            ));
+        }
+
+        // A repository lists its files in the snapshot index, and an
+        // encrypted backup in its manifest
+        let mut files_to_restore: Vec<PathBuf> = Vec::new();
+        if let (true, Some(index)) = (repository.is_some() || cipher.is_some(), &manifest) {
+            files_to_restore.extend(
+                index
+                    .files
+                    .keys()
+                    .map(PathBuf::from)
+                    .filter(|path| !filter.is_file_excluded(path)),
+            );
+        } else {
+            let walker = WalkDir::new(&backup_dir).into_iter().filter_entry(|entry| {
+                let relative_path = entry
+                    .path()
+                    .strip_prefix(&backup_dir)
+                    .unwrap_or(entry.path());
+                !filter.is_excluded(relative_path, entry.file_type().is_dir())
+            });
+            for entry in walker {
+                match entry {
+                    // The backup directory itself is the destination
+                    Ok(entry)
+                        if entry.depth() > 0
+                            && (entry.file_type().is_file()
+                                || entry.file_type().is_dir()
+                                || entry.file_type().is_symlink()
+                                || special_kind(entry.file_type()).is_some()) =>
+                    {
+                        if let Ok(relative_path) = entry.path().strip_prefix(&backup_dir) {
+                            files_to_restore.push(relative_path.to_path_buf());
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(e) => report
+                        .errors
+                        .push(format!("Error reading backup entry: {}", e)),
+                }
+            }
+        }
+
+        let (mp, pb) = progress_bars(self.progress, files_to_restore.len() as u64);
+
+        // Directories come before their contents in both listings. Hard
+        // links wait until the file they link to is restored.
+        let mut dirs = Vec::new();
+        let mut hard_links = Vec::new();
+        let mut restored = HashMap::new();
+        for relative_path in &files_to_restore {
+            let recorded = manifest.as_ref().and_then(|m| m.get(relative_path));
+            if let Some(first) = recorded.and_then(|entry| entry.hard_link.as_ref()) {
+                hard_links.push((relative_path, recorded, first));
+                continue;
+            }
+            restored.insert(key(relative_path), report.files.len());
+            let file = if is_dir_entry(&backup_dir, relative_path, recorded) {
+                dirs.push((report.files.len(), recorded));
+                self.restore_dir(relative_path)
+            } else {
+                self.restore_file(
+                    &backup_dir,
+                    relative_path,
+                    recorded,
+                    repository.as_ref(),
+                    cipher.as_ref(),
+                    &mp,
+                )
+            };
+            report.files.push(file);
+            pb.inc(1);
+        }
+
+        // A file whose first name was not restored is restored as a copy
+        for (relative_path, recorded, first) in hard_links {
+            let file = match restored.get(first).map(|&index| &report.files[index]) {
+                Some(first) if !first.is_failed() => {
+                    self.restore_hard_link(relative_path, &first.path, recorded)
+                }
+                _ => self.restore_file(
+                    &backup_dir,
+                    relative_path,
+                    recorded,
+                    repository.as_ref(),
+                    cipher.as_ref(),
+                    &mp,
+                ),
+            };
+            report.files.push(file);
+            pb.inc(1);
+        }
+
+        // Restoring the contents changes a directory's times, so its
+        // attributes are applied afterwards, deepest first
+        if !self.dry_run && !self.preserve.is_empty() {
+            for (index, recorded) in dirs.into_iter().rev() {
+                let file = &mut report.files[index];
+                if file.is_failed() {
+                    continue;
+                }
+                let dst = self.destination.join(&file.path);
+                let result = match recorded {
+                    Some(entry) => self.apply_recorded(&dst, entry),
+                    None => {
+                        let path = backup_dir.join(&file.path);
+                        fs::metadata(&path).and_then(|metadata| {
+                            let xattrs = read_xattrs(&path, self.preserve)?;
+                            apply_attributes(&metadata, &xattrs, &dst, self.preserve)
+                        })
+                    }
+                };
+                if let Err(e) = result {
+                    file.error = Some(format!("Error setting directory attributes: {}", e));
+                }
+            }
+        }
+
+        pb.finish_with_message("Restore completed.");
+
+        report.finished = SystemTime::now();
+        Ok(report)
+    }
+
+    fn restore_file(
+        &self,
+        backup_dir: &Path,
+        relative_path: &Path,
+        recorded: Option<&ManifestEntry>,
+        repository: Option<&Repository>,
+        cipher: Option<&Cipher>,
+        mp: &MultiProgress,
+    ) -> FileReport {
+        let mut file = FileReport::pending(relative_path);
+
+        let path = match cipher.map(|cipher| cipher.encrypt_path(relative_path)) {
+            Some(Ok(stored_path)) => backup_dir.join(stored_path),
+            Some(Err(e)) => {
+                file.error = Some(format!("Error encrypting file name: {}", e));
+                return file;
+            }
+            None => backup_dir.join(relative_path),
+        };
+        let dst = self.destination.join(relative_path);
+
+        let link = match recorded {
+            Some(entry) => entry.link.as_ref().map(PathBuf::from),
+            None => fs::read_link(&path).ok(),
+        };
+        if let Some(target) = link {
+            return self.restore_symlink(&target, &dst, file);
+        }
+
+        // Without a manifest entry, the backup copy describes the node
+        let special = match recorded {
+            Some(entry) => entry.kind.is_special().then(|| (entry.clone(), None)),
+            None => fs::symlink_metadata(&path).ok().and_then(|metadata| {
+                let entry = ManifestEntry {
+                    xattrs: read_xattrs(&path, self.preserve).unwrap_or_default(),
+                    ..ManifestEntry::special(&metadata, special_kind(metadata.file_type())?)
+                };
+                Some((entry, Some(metadata)))
+            }),
+        };
+        if let Some((entry, metadata)) = special {
+            return self.restore_special(&entry, metadata.as_ref(), &dst, file);
+        }
+
+        file.action = match compare_with_destination(&path, &dst, recorded) {
+            Ok(Existing::Missing) => FileAction::New,
+            Ok(Existing::Same) => FileAction::Skipped,
+            Ok(Existing::Older) => FileAction::Modified,
+            Ok(Existing::Newer) if self.force => FileAction::Modified,
+            Ok(Existing::Newer) => {
+                file.action = FileAction::Skipped;
+                file.error = Some(
+                    "Destination file is newer than the backup; use --force to overwrite".into(),
+                );
+                return file;
+            }
+            Err(e) => {
+                file.error = Some(format!("Error comparing with destination: {}", e));
+                return file;
+            }
+        };
+
+        if file.action == FileAction::Skipped || self.dry_run {
+            return file;
+        }
+
+        if let Some(parent) = dst.parent() {
+            if let Err(e) = fs::create_dir_all(parent) {
+                file.error = Some(format!("Error creating directories: {}", e));
+                return file;
+            }
+        }
+
+        // Remove read-only attribute on Windows
+        #[cfg(target_os = "windows")]
+        {
+            if dst.exists() {
+                if let Err(e) = crate::copy::remove_readonly_attribute(&dst) {
+                    file.error = Some(format!("Error removing read-only attribute: {}", e));
+                    return file;
+                }
+            }
+        }
+
+        // Recorded attributes take the place of the backup copy's
+        let preserve = match recorded {
+            Some(_) => Preserve {
+                times: false,
+                permissions: false,
+                xattrs: false,
+                acls: false,
+                ..self.preserve
+            },
+            None => self.preserve,
+        };
+
+        let transform = match recorded {
+            Some(entry) if !entry.compression.is_none() || cipher.is_some() => {
+                Transform::Decode(entry.compression, cipher)
+            }
+            _ => Transform::None,
+        };
+
+        let result = match (repository, recorded) {
+            (Some(repository), Some(entry)) => {
+                repository.restore_file(entry, &dst, relative_path, mp)
+            }
+            _ => copy_with_progress(
+                &path,
+                &dst,
+                relative_path,
+                transform,
+                preserve,
+                recorded.map(|entry| entry.hash.as_str()),
+                "Restoring",
+                mp,
+            )
+            .map(|(bytes, _)| bytes),
+        };
+
+        match result {
+            Ok(bytes) => {
+                file.bytes = bytes;
+                if let Some(entry) = recorded {
+                    if let Err(e) = self.apply_recorded(&dst, entry) {
+                        file.error = Some(format!("Error setting file attributes: {}", e));
+                    }
+                }
+            }
+            Err(e) => file.error = Some(format!("Error restoring file: {}", e)),
+        }
+
+        file
+    }
+
+    /// Creates the directory at `relative_path` in the destination.
+    fn restore_dir(&self, relative_path: &Path) -> FileReport {
+        let mut file = FileReport::pending(relative_path);
+
+        let dst = self.destination.join(relative_path);
+        file.action = match fs::symlink_metadata(&dst) {
+            Ok(existing) if existing.is_dir() => FileAction::Skipped,
+            Ok(_) => {
+                file.error = Some("Destination exists and is not a directory".to_string());
+                return file;
+            }
+            Err(_) => FileAction::New,
+        };
+
+        if self.dry_run {
+            return file;
+        }
+        if file.action == FileAction::New {
+            if let Err(e) = fs::create_dir_all(&dst) {
+                file.error = Some(format!("Error creating directory: {}", e));
+            }
+        } else if self.preserve.permissions {
+            // Its mode is set again once its contents are restored
+            if let Err(e) = make_writable(&dst) {
+                file.error = Some(format!("Error making directory writable: {}", e));
+            }
+        }
+        file
+    }
+
+    /// Makes `dst` a symbolic link to `target`, replacing a different link.
+    /// Anything else in the way is only replaced with `force`.
+    fn restore_symlink(&self, target: &Path, dst: &Path, mut file: FileReport) -> FileReport {
+        file.action = match fs::read_link(dst) {
+            Ok(existing) if existing == target => FileAction::Skipped,
+            Ok(_) => FileAction::Modified,
+            Err(_) if fs::symlink_metadata(dst).is_err() => FileAction::New,
+            Err(_) if self.force => FileAction::Modified,
+            Err(_) => {
+                file.action = FileAction::Skipped;
+                file.error =
+                    Some("Destination is not a link; use --force to replace it".to_string());
+                return file;
+            }
+        };
+
+        if file.action == FileAction::Skipped || self.dry_run {
+            return file;
+        }
+
+        let result = dst
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| replace_with_symlink(target, dst));
+        if let Err(e) = result {
+            file.error = Some(format!("Error restoring link: {}", e));
+        }
+        file
+    }
+
+    /// Recreates the FIFO, device node or socket described by `entry` at
+    /// `dst`. Its attributes come from the backup copy's `metadata` when
+    /// the manifest has no entry for it. Anything else in the way is only
+    /// replaced with `force`.
+    fn restore_special(
+        &self,
+        entry: &ManifestEntry,
+        metadata: Option<&fs::Metadata>,
+        dst: &Path,
+        mut file: FileReport,
+    ) -> FileReport {
+        file.action = match fs::symlink_metadata(dst) {
+            Ok(existing)
+                if special_kind(existing.file_type()) == Some(entry.kind)
+                    && device_number(&existing) == entry.rdev =>
+            {
+                FileAction::Skipped
+            }
+            Ok(_) if self.force => FileAction::Modified,
+            Ok(_) => {
+                file.action = FileAction::Skipped;
+                file.error = Some(
+                    "Destination is not the same special file; use --force to replace it"
+                        .to_string(),
+                );
+                return file;
+            }
+            Err(_) => FileAction::New,
+        };
+
+        if file.action == FileAction::Skipped || self.dry_run {
+            return file;
+        }
+        if !can_create(entry.kind) {
+            return FileReport::failed(file.path, "Device nodes can only be created by root");
+        }
+
+        let result = dst
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| {
+                replace_with(dst, |tmp| {
+                    make_node(tmp, entry.kind, entry.mode, entry.rdev)?;
+                    match metadata {
+                        Some(metadata) => {
+                            apply_attributes(metadata, &entry.xattrs, tmp, self.preserve)
+                        }
+                        None => self.apply_recorded(tmp, entry),
+                    }
+                })
+            });
+        if let Err(e) = result {
+            file.error = Some(format!("Error restoring special file: {}", e));
+        }
+        file
+    }
+
+    /// Makes `dst` a hard link to the restored file at `first`, unless it is
+    /// one already. A newer file in the way is only replaced with `force`.
+    fn restore_hard_link(
+        &self,
+        relative_path: &Path,
+        first: &Path,
+        recorded: Option<&ManifestEntry>,
+    ) -> FileReport {
+        let mut file = FileReport::pending(relative_path);
+
+        let dst = self.destination.join(relative_path);
+        let first_dst = self.destination.join(first);
+        let same_file = match (fs::metadata(&dst), fs::metadata(&first_dst)) {
+            (Ok(existing), Ok(first)) => {
+                hard_link_id(&existing).is_some() && hard_link_id(&existing) == hard_link_id(&first)
+            }
+            _ => false,
+        };
+
+        file.action = match compare_with_destination(&first_dst, &dst, recorded) {
+            _ if same_file => FileAction::Skipped,
+            Ok(Existing::Missing) => FileAction::New,
+            Ok(Existing::Newer) if !self.force => {
+                file.action = FileAction::Skipped;
+                file.error = Some(
+                    "Destination file is newer than the backup; use --force to overwrite".into(),
+                );
+                return file;
+            }
+            Ok(_) => FileAction::Modified,
+            Err(e) => {
+                file.error = Some(format!("Error comparing with destination: {}", e));
+                return file;
+            }
+        };
+
+        if file.action == FileAction::Skipped || self.dry_run {
+            return file;
+        }
+
+        let result = dst
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| replace_with(&dst, |tmp| fs::hard_link(&first_dst, tmp)));
+        if let Err(e) = result {
+            file.error = Some(format!("Error restoring hard link: {}", e));
+        }
+        file
+    }
+
+    /// Applies the permissions, modification time and extended attributes
+    /// from a manifest entry, as selected by the job's [`Preserve`] setting.
+    /// ACLs go last, as in [`apply_attributes`].
+    fn apply_recorded(&self, dst: &Path, entry: &ManifestEntry) -> io::Result<()> {
+        set_xattrs(dst, &entry.xattrs, self.preserve, false)?;
+        if self.preserve.permissions {
+            set_mode(dst, entry.mode)?;
+        }
+        if self.preserve.times {
+            // Set by path, as in apply_metadata
+            let atime = FileTime::from_last_access_time(&fs::symlink_metadata(dst)?);
+            filetime::set_symlink_file_times(dst, atime, entry.file_time())?;
+        }
+        set_xattrs(dst, &entry.xattrs, self.preserve, true)
+    }
+}
+
+/// Returns whether the backup entry at `relative_path` is a directory,
+/// going by the manifest when it has the entry.
+fn is_dir_entry(backup_dir: &Path, relative_path: &Path, recorded: Option<&ManifestEntry>) -> bool {
+    match recorded {
+        Some(entry) => entry.is_dir(),
+        None => fs::symlink_metadata(backup_dir.join(relative_path))
+            .is_ok_and(|metadata| metadata.is_dir()),
+    }
+}
+
+/// State of a destination file relative to its backup copy.
+enum Existing {
+    Missing,
+    /// Same size and modification time.
+    Same,
+    Older,
+    Newer,
+}
+
+/// Compares `dst` with the backup copy, or with the manifest entry for it
+/// when there is one.
+fn compare_with_destination(
+    backup: &Path,
+    dst: &Path,
+    recorded: Option<&ManifestEntry>,
+) -> io::Result<Existing> {
+    let local = match fs::metadata(dst) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Existing::Missing),
+        Err(e) => return Err(e),
+    };
+    let (backup_modified, backup_len) = match recorded {
+        Some(entry) => (entry.modified(), entry.size),
+        None => {
+            let backup = fs::metadata(backup)?;
+            (backup.modified()?, backup.len())
+        }
+    };
+
+    let local_modified = local.modified()?;
+    Ok(if local_modified > backup_modified {
+        Existing::Newer
+    } else if local_modified == backup_modified && local.len() == backup_len {
+        Existing::Same
+    } else {
+        Existing::Older
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::scratch;
+    use crate::BackupJob;
+
+    #[test]
+    fn damaged_backup_copies_leave_the_destination_untouched() {
+        let dir = scratch("restore-damaged");
+        let (source, backup, destination) = (dir.join("src"), dir.join("out"), dir.join("dst"));
+        fs::create_dir(&source).unwrap();
+        fs::write(source.join("a"), "good data").unwrap();
+        BackupJob::new(&source, &backup).run().unwrap();
+
+        // Same size and time, so only the hash tells the copy is damaged
+        let copy = backup.join("a");
+        let modified = FileTime::from_last_modification_time(&fs::metadata(&copy).unwrap());
+        fs::write(&copy, "evil data").unwrap();
+        filetime::set_file_mtime(&copy, modified).unwrap();
+
+        fs::create_dir(&destination).unwrap();
+        fs::write(destination.join("a"), "local").unwrap();
+        filetime::set_file_mtime(destination.join("a"), FileTime::from_unix_time(1000, 0)).unwrap();
+
+        let report = RestoreJob::new(&backup, &destination).run().unwrap();
+        assert!(report.files[0].is_failed());
+        assert_eq!(fs::read(destination.join("a")).unwrap(), b"local");
+        let leftovers = fs::read_dir(&destination).unwrap().count();
+        assert_eq!(leftovers, 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,134 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use chrono::{Local, NaiveDateTime};
+
+use crate::error::{Error, Result};
+use crate::manifest::Manifest;
+use crate::repository::Repository;
+
+/// Name of the symlink pointing at the newest snapshot.
+pub const LATEST_LINK: &str = "latest";
+
+/// Format of snapshot directory names, e.g. `2026-10-17T02-00-00`.
+pub const SNAPSHOT_FORMAT: &str = "%Y-%m-%dT%H-%M-%S";
+
+/// A timestamped backup generation inside a snapshot target directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    /// Directory name, which doubles as the snapshot id.
+    pub name: String,
+    /// Snapshot directory, or index file in a chunk repository.
+    pub path: PathBuf,
+    /// Local time at which the snapshot was started.
+    pub time: NaiveDateTime,
+}
+
+/// Lists the snapshots found in `root`, oldest first.
+///
+/// Entries whose names are not snapshot timestamps are ignored, as are
+/// directories without a manifest, which are not snapshots but backed-up
+/// data that happens to be named like one. In a chunk repository the
+/// snapshots are the index files under `snapshots/`.
+pub fn list_snapshots(root: &Path) -> io::Result<Vec<Snapshot>> {
+    let repository = Repository::is_repository(root);
+    let dir = if repository {
+        Repository::snapshots_dir(root)
+    } else {
+        root.to_path_buf()
+    };
+
+    let mut snapshots = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = match entry.file_name().into_string() {
+            Ok(name) => name,
+            Err(_) => continue,
+        };
+        let name = match name.strip_suffix(".json") {
+            Some(stem) if repository && entry.file_type()?.is_file() => stem.to_string(),
+            _ if !repository
+                && entry.file_type()?.is_dir()
+                && Manifest::path(&entry.path()).is_file() =>
+            {
+                name
+            }
+            _ => continue,
+        };
+        if let Ok(time) = NaiveDateTime::parse_from_str(&name, SNAPSHOT_FORMAT) {
+            snapshots.push(Snapshot {
+                name,
+                path: entry.path(),
+                time,
+            });
+        }
+    }
+    snapshots.sort_by_key(|s| s.time);
+    Ok(snapshots)
+}
+
+/// Returns whether `root` is a target that snapshot runs write into,
+/// which they mark with the `latest` link.
+pub(crate) fn is_snapshot_target(root: &Path) -> bool {
+    fs::symlink_metadata(root.join(LATEST_LINK)).is_ok()
+}
+
+/// Returns the newest snapshot in `root`, following the `latest` link when
+/// it points at a snapshot.
+pub fn latest_snapshot(root: &Path) -> io::Result<Option<Snapshot>> {
+    let snapshots = list_snapshots(root)?;
+    if let Ok(link) = fs::read_link(root.join(LATEST_LINK)) {
+        let name = link.to_string_lossy();
+        if let Some(snapshot) = snapshots.iter().find(|s| s.name == name) {
+            return Ok(Some(snapshot.clone()));
+        }
+    }
+    Ok(snapshots.into_iter().last())
+}
+
+/// Finds the snapshot called `name` in `root`.
+pub fn find_snapshot(root: &Path, name: &str) -> io::Result<Option<Snapshot>> {
+    Ok(list_snapshots(root)?.into_iter().find(|s| s.name == name))
+}
+
+/// Picks the directory holding the files of the backup at `root`: the
+/// snapshot called `name`, else the newest snapshot, else `root` itself.
+///
+/// Also returns the name of the chosen snapshot.
+pub(crate) fn resolve_backup(root: &Path, name: Option<&str>) -> Result<(PathBuf, Option<String>)> {
+    let snapshot_error = |e| Error::Snapshot(root.to_path_buf(), e);
+
+    if let Some(name) = name {
+        return match find_snapshot(root, name).map_err(snapshot_error)? {
+            Some(snapshot) => Ok((snapshot.path, Some(snapshot.name))),
+            None => Err(Error::SnapshotNotFound(name.to_string())),
+        };
+    }
+
+    match latest_snapshot(root).map_err(snapshot_error)? {
+        Some(snapshot) => Ok((snapshot.path, Some(snapshot.name))),
+        None => Ok((root.to_path_buf(), None)),
+    }
+}
+
+/// Returns the directory name for a snapshot started now.
+pub(crate) fn new_snapshot_name() -> String {
+    Local::now().format(SNAPSHOT_FORMAT).to_string()
+}
+
+/// Points the `latest` link in `root` at the snapshot called `name`.
+///
+/// The link is replaced atomically so it never goes missing.
+pub(crate) fn update_latest(root: &Path, name: &str) -> io::Result<()> {
+    let link = root.join(LATEST_LINK);
+    let tmp = root.join(format!(".{}.srb-tmp", LATEST_LINK));
+    let _ = fs::remove_file(&tmp);
+
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(name, &tmp)?;
+    #[cfg(windows)]
+    std::os::windows::fs::symlink_dir(name, &tmp)?;
+
+    fs::rename(&tmp, &link)
+}
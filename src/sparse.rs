@@ -0,0 +1,107 @@
+use std::fs::{File, Metadata};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use indicatif::ProgressBar;
+
+/// Returns the start and end of each region of `file` holding data when it
+/// has holes, found with `SEEK_DATA` and `SEEK_HOLE`. Returns `None` for a
+/// fully allocated file, or where holes cannot be found.
+///
+/// The offset of `file` is left wherever the search stopped.
+pub(crate) fn data_regions(file: &File, metadata: &Metadata) -> Option<Vec<(u64, u64)>> {
+    #[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+    {
+        use std::os::unix::fs::MetadataExt;
+        use std::os::unix::io::AsRawFd;
+
+        // Blocks are counted in 512-byte units whatever the file system
+        if metadata.blocks().saturating_mul(512) >= metadata.len() {
+            return None;
+        }
+
+        let fd = file.as_raw_fd();
+        let mut regions = Vec::new();
+        let mut offset = 0;
+        while offset < metadata.len() {
+            // SAFETY: lseek only moves the offset of a descriptor that
+            // `file` keeps open.
+            let start = unsafe { libc::lseek(fd, offset as libc::off_t, libc::SEEK_DATA) };
+            if start < 0 {
+                // ENXIO means there is no data after `offset`
+                match io::Error::last_os_error().raw_os_error() {
+                    Some(libc::ENXIO) => break,
+                    _ => return None,
+                }
+            }
+            // SAFETY: as above.
+            let end = unsafe { libc::lseek(fd, start, libc::SEEK_HOLE) };
+            if end < 0 {
+                return None;
+            }
+            regions.push((start as u64, (end as u64).min(metadata.len())));
+            offset = end as u64;
+        }
+        Some(regions)
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd")))]
+    {
+        let _ = (file, metadata);
+        None
+    }
+}
+
+/// Copies the data `regions` of `src`, `len` bytes long, into the new file
+/// `dst`, seeking over the holes between them so that they stay holes.
+///
+/// Holes advance `pb` and are hashed as the zeros they read as, so progress
+/// and hash cover the logical size. Returns the number of bytes covered.
+pub(crate) fn copy_sparse(
+    src: &mut File,
+    dst: &mut File,
+    regions: &[(u64, u64)],
+    len: u64,
+    pb: &ProgressBar,
+    hasher: &mut blake3::Hasher,
+) -> io::Result<u64> {
+    let mut buffer = [0u8; 8192];
+    let mut position = 0;
+    for &(start, end) in regions {
+        skip_hole(start - position, pb, hasher);
+        src.seek(SeekFrom::Start(start))?;
+        dst.seek(SeekFrom::Start(start))?;
+        position = start;
+        while position < end {
+            let wanted = (end - position).min(buffer.len() as u64) as usize;
+            let bytes_read = src.read(&mut buffer[..wanted])?;
+            if bytes_read == 0 {
+                // The file shrank while it was copied
+                return finish(dst, position);
+            }
+            dst.write_all(&buffer[..bytes_read])?;
+            hasher.update(&buffer[..bytes_read]);
+            pb.inc(bytes_read as u64);
+            position += bytes_read as u64;
+        }
+    }
+    skip_hole(len - position, pb, hasher);
+    finish(dst, len)
+}
+
+/// Accounts for a hole of `len` bytes.
+fn skip_hole(len: u64, pb: &ProgressBar, hasher: &mut blake3::Hasher) {
+    static ZEROS: [u8; 65536] = [0; 65536];
+    let mut remaining = len;
+    while remaining > 0 {
+        let chunk = remaining.min(ZEROS.len() as u64);
+        hasher.update(&ZEROS[..chunk as usize]);
+        remaining -= chunk;
+    }
+    pb.inc(len);
+}
+
+/// Sets the length of `dst`, which leaves a hole at its end if the last
+/// region ended before `len`.
+fn finish(dst: &mut File, len: u64) -> io::Result<u64> {
+    dst.set_len(len)?;
+    Ok(len)
+}
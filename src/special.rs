@@ -0,0 +1,114 @@
+use std::fs::{FileType, Metadata};
+use std::io;
+use std::path::Path;
+
+use crate::manifest::EntryKind;
+
+/// Returns the kind of a FIFO, device node or socket, or `None` for any
+/// other file type.
+pub(crate) fn special_kind(file_type: FileType) -> Option<EntryKind> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        if file_type.is_fifo() {
+            return Some(EntryKind::Fifo);
+        } else if file_type.is_char_device() {
+            return Some(EntryKind::CharDevice);
+        } else if file_type.is_block_device() {
+            return Some(EntryKind::BlockDevice);
+        } else if file_type.is_socket() {
+            return Some(EntryKind::Socket);
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = file_type;
+    None
+}
+
+/// Returns the device number of a device node, or 0.
+pub(crate) fn device_number(metadata: &Metadata) -> u64 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        metadata.rdev()
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        0
+    }
+}
+
+/// Returns the (device, inode) pair identifying a regular file that has
+/// other hard links, or `None` when it has a single name.
+pub(crate) fn hard_link_id(metadata: &Metadata) -> Option<(u64, u64)> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        (metadata.is_file() && metadata.nlink() > 1).then(|| (metadata.dev(), metadata.ino()))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        None
+    }
+}
+
+/// Returns whether this process may create a node of `kind`. Device nodes
+/// need root.
+pub(crate) fn can_create(kind: EntryKind) -> bool {
+    #[cfg(unix)]
+    {
+        match kind {
+            EntryKind::CharDevice | EntryKind::BlockDevice => crate::metadata::is_root(),
+            _ => kind.is_special(),
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = kind;
+        false
+    }
+}
+
+/// Creates a FIFO, device node or socket at `path` with mknod.
+#[cfg(unix)]
+pub(crate) fn make_node(path: &Path, kind: EntryKind, mode: u32, rdev: u64) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let file_type = match kind {
+        EntryKind::Fifo => libc::S_IFIFO,
+        EntryKind::CharDevice => libc::S_IFCHR,
+        EntryKind::BlockDevice => libc::S_IFBLK,
+        EntryKind::Socket => libc::S_IFSOCK,
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "not a special file",
+            ))
+        }
+    };
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    // mode_t and dev_t differ in width between platforms
+    #[allow(clippy::unnecessary_cast, clippy::useless_conversion)]
+    let (mode, rdev) = (
+        file_type | (mode & 0o7777) as libc::mode_t,
+        rdev as libc::dev_t,
+    );
+    // SAFETY: `path` is a valid NUL-terminated string for the duration of
+    // the call.
+    if unsafe { libc::mknod(path.as_ptr(), mode, rdev) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn make_node(_path: &Path, _kind: EntryKind, _mode: u32, _rdev: u64) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "special files are not supported on this platform",
+    ))
+}
@@ -0,0 +1,107 @@
+use std::io;
+use std::path::{Component, Path};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::copy::replace_with;
+
+/// What a backup does with symbolic links found in the source.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SymlinkMode {
+    /// Record links as links and recreate them in the target.
+    #[default]
+    Preserve,
+    /// Back up what links point to, descending into linked directories.
+    Follow,
+    /// Leave links out.
+    Skip,
+}
+
+/// Returns whether a link at `relative_path` inside the source, pointing
+/// at `target`, leads outside the source directory.
+///
+/// The check is made on the link text alone, so it also works for links
+/// that do not resolve. Absolute targets always count as outside, as with
+/// rsync's `--safe-links`.
+pub(crate) fn points_outside(relative_path: &Path, target: &Path) -> bool {
+    let mut depth = relative_path.components().count().saturating_sub(1);
+    for component in target.components() {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+            Component::ParentDir if depth > 0 => depth -= 1,
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return true,
+        }
+    }
+    false
+}
+
+/// Makes `path` a symbolic link to `target`, replacing any file or link
+/// already there.
+pub(crate) fn replace_with_symlink(target: &Path, path: &Path) -> io::Result<()> {
+    replace_with(path, |tmp| create_symlink(target, tmp))
+}
+
+#[cfg(unix)]
+fn create_symlink(target: &Path, path: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, path)
+}
+
+#[cfg(windows)]
+fn create_symlink(target: &Path, path: &Path) -> io::Result<()> {
+    // Windows needs to know whether the link is to a directory
+    let resolved = path.parent().unwrap_or(Path::new(".")).join(target);
+    if resolved.is_dir() {
+        std::os::windows::fs::symlink_dir(target, path)
+    } else {
+        std::os::windows::fs::symlink_file(target, path)
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn create_symlink(_target: &Path, _path: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "symbolic links are not supported on this platform",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn outside(relative_path: &str, target: &str) -> bool {
+        points_outside(Path::new(relative_path), Path::new(target))
+    }
+
+    #[test]
+    fn parent_directories_are_counted_from_the_link() {
+        assert!(!outside("a/b/link", "../c"));
+        assert!(!outside("a/b/link", "../../c"));
+        assert!(outside("a/b/link", "../../../c"));
+        assert!(outside("link", "../c"));
+    }
+
+    #[test]
+    fn nested_parent_directories_are_followed_in_order() {
+        assert!(!outside("a/link", "b/../../c"));
+        assert!(outside("a/link", "b/../../../c"));
+        // Leaving and coming back still leaves the source on the way
+        assert!(outside("link", "../source/c"));
+    }
+
+    #[test]
+    fn absolute_targets_are_outside() {
+        assert!(outside("link", "/etc/passwd"));
+        assert!(outside("a/b/link", "/"));
+    }
+
+    #[test]
+    fn current_directories_are_ignored() {
+        assert!(!outside("link", "./c"));
+        assert!(!outside("a/link", "./.././c"));
+        assert!(outside("link", "./../c"));
+    }
+}
@@ -0,0 +1,662 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use walkdir::WalkDir;
+
+use crate::compare::{hash_file, hash_reader};
+use crate::compress::{decoder, Compression};
+use crate::copy::progress_bars;
+use crate::crypto::{decrypter, unlock, Cipher, EncryptionKey};
+use crate::error::{Error, Result};
+use crate::filter::Filter;
+use crate::job::{prepare_sources, Source};
+use crate::manifest::{Manifest, ManifestEntry};
+use crate::repository::Repository;
+use crate::snapshot::resolve_backup;
+use crate::symlink::SymlinkMode;
+
+/// Result of checking one file of a backup.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerifyStatus {
+    /// The backup copy matches the source.
+    Ok,
+    /// The file exists in the source, or is listed in the manifest, but is
+    /// not in the backup.
+    Missing,
+    /// The file exists in the backup but not in the source or manifest.
+    Extra,
+    /// Sizes or contents differ.
+    Mismatched,
+}
+
+impl fmt::Display for VerifyStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            VerifyStatus::Ok => "ok",
+            VerifyStatus::Missing => "missing",
+            VerifyStatus::Extra => "extra",
+            VerifyStatus::Mismatched => "mismatch",
+        };
+        f.pad(name)
+    }
+}
+
+/// The outcome for one file found in the source or the backup.
+#[derive(Debug, Clone)]
+pub struct VerifyEntry {
+    /// Path relative to the source and backup directories.
+    pub path: PathBuf,
+    pub status: VerifyStatus,
+    /// Explains a mismatch, e.g. which sizes differ.
+    pub detail: Option<String>,
+}
+
+/// Summary of a [`VerifyJob`] run.
+#[derive(Debug, Clone)]
+pub struct VerifyReport {
+    /// Source directories compared against; empty when the backup was
+    /// checked against its manifest.
+    pub sources: Vec<PathBuf>,
+    /// Directory that was checked: the target, or the verified snapshot.
+    pub backup: PathBuf,
+    pub snapshot: Option<String>,
+    /// One entry per file, sorted by path.
+    pub entries: Vec<VerifyEntry>,
+    /// Files that could not be read.
+    pub errors: Vec<String>,
+}
+
+impl VerifyReport {
+    /// Entries whose status is not [`VerifyStatus::Ok`].
+    pub fn problems(&self) -> impl Iterator<Item = &VerifyEntry> {
+        self.entries.iter().filter(|e| e.status != VerifyStatus::Ok)
+    }
+
+    pub fn count(&self, status: VerifyStatus) -> usize {
+        self.entries.iter().filter(|e| e.status == status).count()
+    }
+
+    /// Returns true when every file matched and nothing failed.
+    pub fn is_success(&self) -> bool {
+        self.errors.is_empty() && self.problems().next().is_none()
+    }
+}
+
+/// Checks that a backup still matches its source, or the manifest written
+/// when it was made.
+///
+/// For a chunk repository, the snapshot index takes the place of the
+/// manifest: every chunk of every file is read back and checked against
+/// its hash, and with a source the index is compared with the source too. Compressed and encrypted copies are decoded
+/// before they are compared.
+///
+/// Both trees are walked with the same filters as the backup; files present
+/// on both sides are compared by size and then by BLAKE3 content hash.
+/// Only regular files are checked, not symbolic links.
+#[derive(Debug, Clone)]
+pub struct VerifyJob {
+    /// Empty to check against the manifest.
+    sources: Vec<PathBuf>,
+    target: PathBuf,
+    snapshot: Option<String>,
+    include: Vec<String>,
+    exclude: Vec<String>,
+    progress: bool,
+    key: Option<EncryptionKey>,
+    symlinks: SymlinkMode,
+}
+
+impl VerifyJob {
+    /// Creates a job checking the backup in `target` against `source`.
+    pub fn new(source: impl Into<PathBuf>, target: impl Into<PathBuf>) -> Self {
+        VerifyJob {
+            sources: vec![source.into()],
+            target: target.into(),
+            snapshot: None,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            progress: false,
+            key: None,
+            symlinks: SymlinkMode::default(),
+        }
+    }
+
+    /// Creates a job checking the backup in `target` against the sizes and
+    /// hashes recorded in its manifest, without needing the source.
+    pub fn with_manifest(target: impl Into<PathBuf>) -> Self {
+        VerifyJob {
+            sources: Vec::new(),
+            ..VerifyJob::new(PathBuf::new(), target)
+        }
+    }
+
+    /// Adds another directory the backup was made from.
+    ///
+    /// As in [`BackupJob::add_source`](crate::BackupJob::add_source), each
+    /// source is expected in a subtree of the backup named after its last
+    /// path component.
+    pub fn add_source(mut self, source: impl Into<PathBuf>) -> Self {
+        self.sources.push(source.into());
+        self
+    }
+
+    /// Verifies the snapshot with this id instead of the newest one.
+    pub fn snapshot(mut self, name: impl Into<String>) -> Self {
+        self.snapshot = Some(name.into());
+        self
+    }
+
+    /// Adds an include glob, as given to the backup.
+    pub fn include(mut self, pattern: impl Into<String>) -> Self {
+        self.include.push(pattern.into());
+        self
+    }
+
+    /// Adds an exclude glob, as given to the backup.
+    pub fn exclude(mut self, pattern: impl Into<String>) -> Self {
+        self.exclude.push(pattern.into());
+        self
+    }
+
+    /// Sets how symbolic links in the source are treated, as given to the
+    /// backup. With [`SymlinkMode::Follow`], the files that links point to
+    /// are expected in the backup.
+    pub fn symlinks(mut self, mode: SymlinkMode) -> Self {
+        self.symlinks = mode;
+        self
+    }
+
+    /// Draws a progress bar on the terminal while the job runs.
+    pub fn progress(mut self, enabled: bool) -> Self {
+        self.progress = enabled;
+        self
+    }
+
+    /// Decrypts an encrypted backup with `key`, the passphrase or key file
+    /// it was made with.
+    pub fn key(mut self, key: EncryptionKey) -> Self {
+        self.key = Some(key);
+        self
+    }
+
+    pub fn run(&self) -> Result<VerifyReport> {
+        let sources = prepare_sources(&self.sources, &self.include, &self.exclude)?;
+        if !self.target.is_dir() {
+            return Err(Error::TargetNotDir(self.target.clone()));
+        }
+
+        let (backup_dir, snapshot) = resolve_backup(&self.target, self.snapshot.as_deref())?;
+
+        let mut warnings = Vec::new();
+
+        // A repository snapshot is checked through its index and chunks
+        let stored = if Repository::is_repository(&self.target) {
+            if snapshot.is_none() {
+                return Err(Error::Snapshot(
+                    self.target.clone(),
+                    io::Error::new(io::ErrorKind::NotFound, "the repository has no snapshots"),
+                ));
+            }
+            let repository = Repository::open(&self.target)
+                .map_err(|e| Error::Repository(self.target.clone(), e))?;
+            let index = Manifest::read(&backup_dir, None)
+                .map_err(|e| Error::Manifest(backup_dir.clone(), e))?;
+            Stored::Repository(repository, index)
+        } else {
+            let cipher = unlock(&self.target, self.key.as_ref())?;
+            let manifest = match Manifest::load_with(&backup_dir, cipher.as_ref()) {
+                Ok(manifest) => manifest,
+                // Against the source, the manifest of an unencrypted backup
+                // only tells which files are compressed
+                Err(e) if !sources.is_empty() && cipher.is_none() => {
+                    warnings.push(format!(
+                        "Error reading manifest {:?}, treating files as uncompressed: {}",
+                        Manifest::path(&backup_dir),
+                        e
+                    ));
+                    None
+                }
+                Err(e) => return Err(Error::Manifest(Manifest::path(&backup_dir), e)),
+            };
+            Stored::Files {
+                dir: backup_dir.clone(),
+                manifest,
+                cipher,
+            }
+        };
+
+        // Against the manifest, the backup is filtered as one whole tree
+        let whole_backup = || -> Result<Vec<Source>> {
+            Ok(vec![Source {
+                dir: backup_dir.clone(),
+                subtree: PathBuf::new(),
+                filter: Filter::new(&backup_dir, &self.include, &self.exclude)?
+                    .without_ignore_files(),
+            }])
+        };
+        let (reference, mut sources) = match (sources.is_empty(), &stored) {
+            (false, _) => (Reference::Source, sources),
+            (true, Stored::Repository(_, index)) => {
+                (Reference::Manifest(index.clone()), whole_backup()?)
+            }
+            (true, Stored::Files { manifest, .. }) => {
+                let manifest = manifest.clone().ok_or_else(|| {
+                    Error::Manifest(
+                        Manifest::path(&backup_dir),
+                        io::Error::new(io::ErrorKind::NotFound, "the backup has no manifest"),
+                    )
+                })?;
+                (Reference::Manifest(manifest), whole_backup()?)
+            }
+        };
+
+        let mut report = VerifyReport {
+            sources: self.sources.clone(),
+            backup: backup_dir.clone(),
+            snapshot,
+            entries: Vec::new(),
+            errors: warnings,
+        };
+
+        let mut expected_files = BTreeSet::new();
+        let mut backup_files = BTreeSet::new();
+        for source in &mut sources {
+            if matches!(reference, Reference::Source) {
+                let files = list_files(
+                    &source.dir,
+                    &mut source.filter,
+                    None,
+                    self.symlinks == SymlinkMode::Follow,
+                    &mut report.errors,
+                );
+                expected_files.extend(files.iter().map(|path| source.target_path(path)));
+            }
+            if let Stored::Files { dir, cipher, .. } = &stored {
+                let root = match stored_path(dir, &source.subtree, cipher.as_ref()) {
+                    Ok(root) => root,
+                    Err(e) => {
+                        report.errors.push(format!(
+                            "{}: Error encrypting name: {}",
+                            source.subtree.display(),
+                            e
+                        ));
+                        continue;
+                    }
+                };
+                // A source missing from the backup leaves all its files missing
+                if root.is_dir() {
+                    let files = list_files(
+                        &root,
+                        &mut source.filter,
+                        cipher.as_ref(),
+                        false,
+                        &mut report.errors,
+                    );
+                    backup_files.extend(files.iter().map(|path| source.target_path(path)));
+                }
+            }
+        }
+        if let Reference::Manifest(manifest) = &reference {
+            expected_files = manifest_files(manifest, &mut sources);
+        }
+        if let Stored::Repository(_, index) = &stored {
+            backup_files = manifest_files(index, &mut sources);
+        }
+        for source in &mut sources {
+            report.errors.append(&mut source.filter.warnings);
+        }
+
+        let (_mp, pb) = progress_bars(self.progress, expected_files.len() as u64);
+        pb.set_message("Verifying...");
+
+        for path in expected_files.union(&backup_files) {
+            let entry = |status, detail| VerifyEntry {
+                path: path.clone(),
+                status,
+                detail,
+            };
+
+            if !backup_files.contains(path) {
+                report.entries.push(entry(VerifyStatus::Missing, None));
+                pb.inc(1);
+                continue;
+            }
+            if !expected_files.contains(path) {
+                report.entries.push(entry(VerifyStatus::Extra, None));
+                continue;
+            }
+
+            match check_file(&reference, &stored, &sources, path) {
+                Ok(None) => report.entries.push(entry(VerifyStatus::Ok, None)),
+                Ok(Some(detail)) => report
+                    .entries
+                    .push(entry(VerifyStatus::Mismatched, Some(detail))),
+                Err(e) => {
+                    report
+                        .errors
+                        .push(format!("{}: Error reading file: {}", path.display(), e))
+                }
+            }
+            pb.inc(1);
+        }
+
+        pb.finish_with_message("Verify completed.");
+
+        Ok(report)
+    }
+}
+
+/// What the backup is checked against.
+enum Reference {
+    /// The source directories the job was given.
+    Source,
+    Manifest(Manifest),
+}
+
+/// How the backed-up files are stored.
+enum Stored {
+    /// Files in a directory, with the manifest if there is one.
+    Files {
+        dir: PathBuf,
+        manifest: Option<Manifest>,
+        cipher: Option<Cipher>,
+    },
+    Repository(Repository, Manifest),
+}
+
+/// Checks one file present on both sides, returning a description of how
+/// the backup differs from the reference, if it does.
+fn check_file(
+    reference: &Reference,
+    stored: &Stored,
+    sources: &[Source],
+    path: &Path,
+) -> io::Result<Option<String>> {
+    match (reference, stored) {
+        (
+            Reference::Source,
+            Stored::Files {
+                dir,
+                manifest,
+                cipher,
+            },
+        ) => {
+            let compression = manifest
+                .as_ref()
+                .and_then(|manifest| manifest.get(path))
+                .map_or(Compression::None, |recorded| recorded.compression);
+            let backup = stored_path(dir, path, cipher.as_ref())?;
+            damage_as_mismatch(compare_files(
+                &source_path(sources, path)?,
+                &backup,
+                compression,
+                cipher.as_ref(),
+            ))
+        }
+        (Reference::Source, Stored::Repository(repository, index)) => {
+            match index.get(path) {
+                // The chunks must hold what the index records, which must
+                // match the source
+                Some(recorded) => match repository.check_file(recorded)? {
+                    Some(detail) => Ok(Some(detail)),
+                    None => compare_with_manifest(
+                        recorded,
+                        &source_path(sources, path)?,
+                        Compression::None,
+                        None,
+                        "source",
+                    ),
+                },
+                None => Ok(None),
+            }
+        }
+        (Reference::Manifest(manifest), Stored::Files { dir, cipher, .. }) => {
+            match manifest.get(path) {
+                Some(recorded) => damage_as_mismatch(compare_with_manifest(
+                    recorded,
+                    &stored_path(dir, path, cipher.as_ref())?,
+                    recorded.compression,
+                    cipher.as_ref(),
+                    "backup",
+                )),
+                None => Ok(None),
+            }
+        }
+        (Reference::Manifest(_), Stored::Repository(repository, index)) => match index.get(path) {
+            Some(recorded) => repository.check_file(recorded),
+            None => Ok(None),
+        },
+    }
+}
+
+/// Returns the file in the source directories that the backup stores at
+/// `relative_path`.
+fn source_path(sources: &[Source], relative_path: &Path) -> io::Result<PathBuf> {
+    sources
+        .iter()
+        .find_map(|source| {
+            let path = relative_path.strip_prefix(&source.subtree).ok()?;
+            Some(source.dir.join(path))
+        })
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "not under any source"))
+}
+
+/// Returns where the copy of the file at `relative_path` is stored in
+/// `dir`.
+fn stored_path(dir: &Path, relative_path: &Path, cipher: Option<&Cipher>) -> io::Result<PathBuf> {
+    match cipher {
+        Some(cipher) => Ok(dir.join(cipher.encrypt_path(relative_path)?)),
+        None => Ok(dir.join(relative_path)),
+    }
+}
+
+/// Reports backup data that fails to decode, such as an encrypted copy
+/// whose authentication fails, as a mismatch rather than a read error.
+fn damage_as_mismatch(result: io::Result<Option<String>>) -> io::Result<Option<String>> {
+    match result {
+        Err(e) if e.kind() == io::ErrorKind::InvalidData => Ok(Some(e.to_string())),
+        result => result,
+    }
+}
+
+/// Collects the paths of the regular files recorded in `manifest` that lie
+/// in the subtree of one of `sources` and pass its filter, in sorted order.
+fn manifest_files(manifest: &Manifest, sources: &mut [Source]) -> BTreeSet<PathBuf> {
+    manifest
+        .files
+        .iter()
+        .filter(|(_, entry)| entry.is_file())
+        .map(|(path, _)| PathBuf::from(path))
+        .filter(|path| {
+            sources
+                .iter_mut()
+                .any(|source| match path.strip_prefix(&source.subtree) {
+                    Ok(relative_path) => !source.filter.is_file_excluded(relative_path),
+                    Err(_) => false,
+                })
+        })
+        .collect()
+}
+
+/// Collects the relative paths of the files under `root` that pass
+/// `filter`, in sorted order, descending into linked directories with
+/// `follow_links`. With a `cipher`, encrypted names are decrypted; names
+/// that do not decrypt are listed as they are.
+fn list_files(
+    root: &Path,
+    filter: &mut Filter,
+    cipher: Option<&Cipher>,
+    follow_links: bool,
+    errors: &mut Vec<String>,
+) -> BTreeSet<PathBuf> {
+    let plain_path = |stored_path: &Path| match cipher {
+        Some(cipher) => cipher
+            .decrypt_path(stored_path)
+            .unwrap_or_else(|_| stored_path.to_path_buf()),
+        None => stored_path.to_path_buf(),
+    };
+
+    let mut files = BTreeSet::new();
+    let walker = WalkDir::new(root)
+        .follow_links(follow_links)
+        .into_iter()
+        .filter_entry(|entry| {
+            let relative_path = entry.path().strip_prefix(root).unwrap_or(entry.path());
+            !filter.is_excluded(&plain_path(relative_path), entry.file_type().is_dir())
+        });
+    for entry in walker {
+        match entry {
+            Ok(entry) if entry.file_type().is_file() => {
+                if let Ok(relative_path) = entry.path().strip_prefix(root) {
+                    files.insert(plain_path(relative_path));
+                }
+            }
+            Ok(_) => {}
+            Err(e) => errors.push(format!("Error reading entry: {}", e)),
+        }
+    }
+    files
+}
+
+/// Returns a description of how the two files differ, if they do. The
+/// backup copy is decrypted with `cipher` and decompressed with
+/// `compression` first.
+fn compare_files(
+    source: &Path,
+    backup: &Path,
+    compression: Compression,
+    cipher: Option<&Cipher>,
+) -> io::Result<Option<String>> {
+    let source_len = fs::metadata(source)?.len();
+    let size_differs =
+        |backup_len| format!("size {} in source, {} in backup", source_len, backup_len);
+
+    // The size of an encoded copy says nothing about its contents
+    if compression.is_none() && cipher.is_none() {
+        let backup_len = fs::metadata(backup)?.len();
+        if source_len != backup_len {
+            return Ok(Some(size_differs(backup_len)));
+        }
+    }
+
+    let (backup_len, backup_hash) = hash_reader(decoder(
+        decrypter(File::open(backup)?, cipher)?,
+        compression,
+    )?)?;
+    if source_len != backup_len {
+        return Ok(Some(size_differs(backup_len)));
+    }
+    if hash_file(source)? != backup_hash {
+        return Ok(Some("content differs".to_string()));
+    }
+
+    Ok(None)
+}
+
+/// Returns a description of how the file at `path`, on the side called
+/// `side`, differs from what the manifest recorded, if it does. The file
+/// is decrypted with `cipher` and decompressed with `compression` first.
+fn compare_with_manifest(
+    recorded: &ManifestEntry,
+    path: &Path,
+    compression: Compression,
+    cipher: Option<&Cipher>,
+    side: &str,
+) -> io::Result<Option<String>> {
+    let size_differs = |len| format!("size {} in manifest, {} in {}", recorded.size, len, side);
+
+    if compression.is_none() && cipher.is_none() {
+        let len = fs::metadata(path)?.len();
+        if recorded.size != len {
+            return Ok(Some(size_differs(len)));
+        }
+    }
+
+    let (len, hash) = hash_reader(decoder(decrypter(File::open(path)?, cipher)?, compression)?)?;
+    if recorded.size != len {
+        return Ok(Some(size_differs(len)));
+    }
+    if hash.to_hex().as_str() != recorded.hash {
+        return Ok(Some("content differs from manifest".to_string()));
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::scratch;
+    use crate::BackupJob;
+
+    #[test]
+    fn repository_chunks_are_checked_against_the_source() {
+        let dir = scratch("verify-chunks");
+        let (source, target) = (dir.join("src"), dir.join("repo"));
+        fs::create_dir(&source).unwrap();
+        fs::write(source.join("a"), "some data").unwrap();
+        BackupJob::new(&source, &target)
+            .repository(true)
+            .run()
+            .unwrap();
+
+        let report = VerifyJob::new(&source, &target).run().unwrap();
+        assert!(report.is_success());
+
+        for chunk in WalkDir::new(target.join("chunks")) {
+            let chunk = chunk.unwrap();
+            if chunk.file_type().is_file() {
+                fs::remove_file(chunk.path()).unwrap();
+            }
+        }
+        let report = VerifyJob::new(&source, &target).run().unwrap();
+        assert!(!report.is_success());
+        assert_eq!(report.count(VerifyStatus::Mismatched), 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn several_sources_are_checked_in_their_subtrees() {
+        let dir = scratch("verify-sources");
+        let (etc, srv, target) = (dir.join("etc"), dir.join("srv"), dir.join("dst"));
+        fs::create_dir(&etc).unwrap();
+        fs::create_dir(&srv).unwrap();
+        fs::write(etc.join("a"), "config").unwrap();
+        fs::write(srv.join("b"), "site").unwrap();
+        BackupJob::new(&etc, &target)
+            .add_source(&srv)
+            .run()
+            .unwrap();
+
+        let verify = || {
+            VerifyJob::new(&etc, &target)
+                .add_source(&srv)
+                .run()
+                .unwrap()
+        };
+        let report = verify();
+        assert!(report.is_success(), "{:?}", report);
+        assert_eq!(report.count(VerifyStatus::Ok), 2);
+
+        fs::write(target.join("srv").join("b"), "defaced").unwrap();
+        fs::remove_file(target.join("etc").join("a")).unwrap();
+        let report = verify();
+        let problems: Vec<_> = report
+            .problems()
+            .map(|entry| (entry.path.clone(), entry.status))
+            .collect();
+        assert_eq!(
+            problems,
+            [
+                (Path::new("etc").join("a"), VerifyStatus::Missing),
+                (Path::new("srv").join("b"), VerifyStatus::Mismatched),
+            ]
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
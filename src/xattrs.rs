@@ -0,0 +1,159 @@
+use std::collections::BTreeMap;
+use std::fs::Metadata;
+use std::io;
+use std::path::Path;
+
+use crate::metadata::{apply_metadata, Preserve};
+
+/// Extended attributes of a file or directory, by name.
+pub(crate) type Xattrs = BTreeMap<String, Vec<u8>>;
+
+/// Prefix of the attributes holding POSIX ACLs, `system.posix_acl_access`
+/// and, on directories, `system.posix_acl_default`.
+const ACL_PREFIX: &str = "system.posix_acl_";
+
+fn is_acl(name: &str) -> bool {
+    name.starts_with(ACL_PREFIX)
+}
+
+fn is_selected(name: &str, preserve: Preserve) -> bool {
+    if is_acl(name) {
+        preserve.acls
+    } else {
+        preserve.xattrs
+    }
+}
+
+/// Reads the extended attributes of `path` that `preserve` selects,
+/// following a symbolic link as the backup does. On a file system without
+/// extended attributes there are none.
+pub(crate) fn read_xattrs(path: &Path, preserve: Preserve) -> io::Result<Xattrs> {
+    let mut xattrs = Xattrs::new();
+    if !(preserve.xattrs || preserve.acls) {
+        return Ok(xattrs);
+    }
+
+    let names = match list(path) {
+        Ok(names) => names,
+        Err(e) if e.kind() == io::ErrorKind::Unsupported => return Ok(xattrs),
+        Err(e) => return Err(e),
+    };
+    for name in names {
+        if !is_selected(&name, preserve) {
+            continue;
+        }
+        // The attribute may have been removed since it was listed
+        if let Some(value) = get(path, &name)? {
+            xattrs.insert(name, value);
+        }
+    }
+    Ok(xattrs)
+}
+
+/// Sets the attributes from `xattrs` that `preserve` selects on `path`: the
+/// ACLs when `acls` is true, the others when it is false.
+pub(crate) fn set_xattrs(
+    path: &Path,
+    xattrs: &Xattrs,
+    preserve: Preserve,
+    acls: bool,
+) -> io::Result<()> {
+    for (name, value) in xattrs {
+        if is_acl(name) == acls && is_selected(name, preserve) {
+            set(path, name, value)?;
+        }
+    }
+    Ok(())
+}
+
+/// Applies the attributes selected by `preserve` from `source` and its
+/// extended attributes `xattrs` to `dst`.
+///
+/// Extended attributes go first, while `dst` can still be written to, and
+/// ACLs last, since setting the permission bits rewrites part of an ACL.
+pub(crate) fn apply_attributes(
+    source: &Metadata,
+    xattrs: &Xattrs,
+    dst: &Path,
+    preserve: Preserve,
+) -> io::Result<()> {
+    set_xattrs(dst, xattrs, preserve, false)?;
+    apply_metadata(source, dst, preserve)?;
+    set_xattrs(dst, xattrs, preserve, true)
+}
+
+/// Returns whether the file system holding `path`, or its nearest existing
+/// parent, can hold extended attributes.
+///
+/// Some file systems list attributes but refuse to set them, so the check
+/// sets and removes a `user.` attribute on the directory.
+pub(crate) fn supports_xattrs(path: &Path) -> bool {
+    const PROBE: &str = "user.srb.probe";
+    let Some(dir) = path.ancestors().find(|dir| dir.exists()) else {
+        return true;
+    };
+    match set(dir, PROBE, b"") {
+        Ok(()) => {
+            let _ = remove(dir, PROBE);
+            true
+        }
+        Err(e) => e.kind() != io::ErrorKind::Unsupported,
+    }
+}
+
+#[cfg(unix)]
+fn list(path: &Path) -> io::Result<Vec<String>> {
+    xattr::list_deref(path)?
+        .map(|name| {
+            name.into_string().map_err(|name| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("extended attribute name {:?} is not valid UTF-8", name),
+                )
+            })
+        })
+        .collect()
+}
+
+#[cfg(unix)]
+fn get(path: &Path, name: &str) -> io::Result<Option<Vec<u8>>> {
+    xattr::get_deref(path, name)
+}
+
+#[cfg(unix)]
+fn set(path: &Path, name: &str, value: &[u8]) -> io::Result<()> {
+    xattr::set(path, name, value)
+}
+
+#[cfg(unix)]
+fn remove(path: &Path, name: &str) -> io::Result<()> {
+    xattr::remove(path, name)
+}
+
+#[cfg(not(unix))]
+fn list(_path: &Path) -> io::Result<Vec<String>> {
+    Err(unsupported())
+}
+
+#[cfg(not(unix))]
+fn get(_path: &Path, _name: &str) -> io::Result<Option<Vec<u8>>> {
+    Err(unsupported())
+}
+
+#[cfg(not(unix))]
+fn set(_path: &Path, _name: &str, _value: &[u8]) -> io::Result<()> {
+    Err(unsupported())
+}
+
+#[cfg(not(unix))]
+fn remove(_path: &Path, _name: &str) -> io::Result<()> {
+    Err(unsupported())
+}
+
+#[cfg(not(unix))]
+fn unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "extended attributes are not supported on this platform",
+    )
+}